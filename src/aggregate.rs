@@ -0,0 +1,240 @@
+use types::{Query, Config, SortField};
+use stats::{self, Sketch, Reservoir};
+use processing::{self, SortValue};
+use lint::Findings;
+use std::collections::HashMap;
+
+/// Sample queries kept per fingerprint
+const SAMPLES: usize = 3;
+
+/// Everything kept of queries with one fingerprint in aggregate mode
+#[derive(Clone)]
+struct Accumulator {
+    count: usize,
+    total_time: f64,
+    max_time: f64,
+    lock_time: f64,
+    rows_sent: i64,
+    rows_examined: i64,
+    full_scans: usize,
+    timestamp_first: i64,
+    timestamp_last: i64,
+    times: Sketch,
+    samples: Reservoir<String>
+}
+
+impl Accumulator {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            total_time: 0.0,
+            max_time: 0.0,
+            lock_time: 0.0,
+            rows_sent: 0,
+            rows_examined: 0,
+            full_scans: 0,
+            timestamp_first: -1,
+            timestamp_last: -1,
+            times: Sketch::new(),
+            samples: Reservoir::new(SAMPLES)
+        }
+    }
+
+    fn add(&mut self, q: Query) {
+        self.count += 1;
+
+        if q.query_time >= 0.0 {
+            self.total_time += q.query_time;
+            self.max_time = self.max_time.max(q.query_time);
+            self.times.add(q.query_time);
+        }
+
+        if q.lock_time >= 0.0 {
+            self.lock_time += q.lock_time;
+        }
+
+        if q.rows_sent >= 0 {
+            self.rows_sent += q.rows_sent;
+        }
+
+        if q.rows_examined >= 0 {
+            self.rows_examined += q.rows_examined;
+        }
+
+        if q.extended_value("full_scan") == Some(1.0) {
+            self.full_scans += 1;
+        }
+
+        if q.timestamp >= 0 {
+            if self.timestamp_first < 0 || q.timestamp < self.timestamp_first {
+                self.timestamp_first = q.timestamp;
+            }
+
+            self.timestamp_last = self.timestamp_last.max(q.timestamp);
+        }
+
+        self.samples.add(q.raw_query);
+    }
+
+    fn merge(&mut self, other: Accumulator) {
+        self.count += other.count;
+        self.total_time += other.total_time;
+        self.max_time = self.max_time.max(other.max_time);
+        self.lock_time += other.lock_time;
+        self.rows_sent += other.rows_sent;
+        self.rows_examined += other.rows_examined;
+        self.full_scans += other.full_scans;
+
+        if other.timestamp_first >= 0 &&
+            (self.timestamp_first < 0 || other.timestamp_first < self.timestamp_first) {
+            self.timestamp_first = other.timestamp_first;
+        }
+
+        self.timestamp_last = self.timestamp_last.max(other.timestamp_last);
+        self.times.merge(&other.times);
+        self.samples.merge(other.samples);
+    }
+
+    /// Only fingerprint, first timestamp and aggregate fields are known here
+    fn sort_value(&self, field: &SortField, fingerprint: &String) -> SortValue {
+        match field {
+            &SortField::Timestamp => SortValue::Num(self.timestamp_first as f64),
+            &SortField::Fingerprint => SortValue::Str(fingerprint.clone()),
+            &SortField::Count => SortValue::Num(self.count as f64),
+            &SortField::TotalTime => SortValue::Num(self.total_time),
+            &SortField::AvgTime => SortValue::Num(self.total_time / self.count as f64),
+            &SortField::MaxTime => SortValue::Num(self.max_time),
+            &SortField::TotalExamined => SortValue::Num(self.rows_examined as f64),
+            &SortField::ExaminedRatio => SortValue::Num(stats::examined_ratio(self.rows_examined, self.rows_sent)),
+            _ => SortValue::Null
+        }
+    }
+
+    fn to_string(&self, index: usize, fingerprint: &String, cnf: &Config) -> String {
+        let format_timestamp = |timestamp: i64|
+            if timestamp >= 0 { cnf.format_timestamp(timestamp) } else { "?".to_string() };
+
+        let mut buf = format!("> #{} | FIRST: {} | LAST: {} | COUNT: {}\n",
+                              index, format_timestamp(self.timestamp_first), format_timestamp(self.timestamp_last), self.count);
+
+        buf.push_str(&format!(">> TOTAL_TIME: {:.6} | AVG_TIME: {:.6} | MAX_TIME: {:.6}\n",
+                              self.total_time, self.total_time / self.count as f64, self.max_time));
+        buf.push_str(&format!(">>> P50_TIME: {:.6} | P95_TIME: {:.6} | P99_TIME: {:.6}\n",
+                              self.times.percentile(50.0), self.times.percentile(95.0), self.times.percentile(99.0)));
+        buf.push_str(&format!(">>>> LOCK_TIME: {:.6} | ROWS_SENT: {} | ROWS_EXAMINED: {} | EXAMINED_PER_SENT: {:.1} | FULL_SCANS: {}",
+                              self.lock_time, self.rows_sent, self.rows_examined,
+                              stats::examined_ratio(self.rows_examined, self.rows_sent), self.full_scans));
+
+        if stats::missing_index(self.count, self.rows_examined, self.rows_sent, self.full_scans) {
+            buf.push_str(" | MISSING_INDEX");
+        }
+
+        buf.push_str("\n");
+        buf.push_str(fingerprint);
+
+        for sample in self.samples.items().iter() {
+            buf.push_str(&format!("\n--- EXAMPLE:\n{}", sample));
+        }
+
+        buf.push_str("\n");
+
+        buf
+    }
+}
+
+/// Per-fingerprint accumulators of one parsed chunk. Only these are kept
+/// in aggregate mode, so memory does not grow with the log size.
+#[derive(Clone)]
+pub struct Aggregate {
+    fingerprints: HashMap<String, Accumulator>,
+    findings: Findings,
+    total: usize,
+    filtered_out: usize
+}
+
+impl Aggregate {
+    pub fn new() -> Self {
+        Self {
+            fingerprints: HashMap::new(),
+            findings: Findings::new(),
+            total: 0,
+            filtered_out: 0
+        }
+    }
+
+    pub fn add(&mut self, q: Query, cnf: &Config) {
+        self.total += 1;
+
+        if !processing::matches(&q, cnf, -1) {
+            self.filtered_out += 1;
+            return;
+        }
+
+        self.findings.add(&q, &cnf.lint);
+        self.fingerprints.entry(q.fingerprint.clone()).or_insert_with(Accumulator::new).add(q);
+    }
+
+    pub fn merge(&mut self, other: Aggregate) {
+        for (fingerprint, acc) in other.fingerprints.into_iter() {
+            match self.fingerprints.remove(&fingerprint) {
+                Some(mut self_acc) => {
+                    self_acc.merge(acc);
+                    self.fingerprints.insert(fingerprint, self_acc);
+                }
+                None => {
+                    self.fingerprints.insert(fingerprint, acc);
+                }
+            }
+        }
+
+        self.findings.merge(other.findings);
+        self.total += other.total;
+        self.filtered_out += other.filtered_out;
+    }
+
+    /// Fingerprints in sort order, with count range and limit applied. Query texts
+    /// are not kept, so the count range is of fingerprints, not texts as without --aggregate.
+    pub fn to_string(&self, cnf: &Config) -> String {
+        let count_filtered: usize = self.fingerprints.values()
+            .filter(|acc| acc.count < cnf.count_min || acc.count > cnf.count_max)
+            .map(|acc| acc.count)
+            .sum();
+
+        let mut rows: Vec<(Vec<SortValue>, &String, &Accumulator)> = self.fingerprints.iter()
+            .filter(|&(_, acc)| acc.count >= cnf.count_min && acc.count <= cnf.count_max)
+            .map(|(fingerprint, acc)|
+                (cnf.sort.iter().map(|key| acc.sort_value(&key.field, fingerprint)).collect(), fingerprint, acc))
+            .collect();
+
+        rows.sort_by(|lhs, rhs| processing::compare_values(&lhs.0, &rhs.0, &cnf.sort)
+            .then_with(|| lhs.1.cmp(rhs.1)));
+
+        let mut buf = String::new();
+        let mut shown: usize = 0;
+
+        for (index, &(_, fingerprint, acc)) in rows.iter().enumerate() {
+            buf.push_str(&acc.to_string(index + 1, fingerprint, cnf));
+            buf.push_str("\n");
+            shown += 1;
+
+            if index == cnf.limit {
+                break;
+            }
+        }
+
+        if cnf.lint.len() > 0 {
+            buf.push_str(&self.findings.to_string());
+            buf.push_str("\n");
+        }
+
+        buf.push_str(&format!("TOTAL: {}\nFINGERPRINTS: {}", self.total, self.fingerprints.len()));
+
+        if self.filtered_out + count_filtered > 0 {
+            buf.push_str(&format!("\nFILTERED: {}", self.filtered_out + count_filtered));
+        }
+
+        buf.push_str(&format!("\nSHOWN: {}", shown));
+
+        buf
+    }
+}
@@ -0,0 +1,276 @@
+use types::{Query, Config};
+use stats;
+use state;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::collections::{HashMap, BTreeMap};
+
+/// Query time deviation used for z-scores when baseline latency did not vary
+const MIN_TIME_STD: f64 = 0.001;
+
+/// New fingerprints shown
+const NEW_SHOWN: usize = 20;
+
+/// How far from baseline a fingerprint must be to be reported
+pub enum Threshold {
+    ZScore(f64),
+    Percent(f64)
+}
+
+impl Threshold {
+    /// Z-score, or percentage when ending with %
+    pub fn parse(value: &str) -> Option<Threshold> {
+        let value = value.trim();
+
+        if value.ends_with('%') {
+            value[..value.len() - 1].parse::<f64>().ok().filter(|pct| *pct > 0.0).map(Threshold::Percent)
+        } else {
+            value.parse::<f64>().ok().filter(|z| *z > 0.0).map(Threshold::ZScore)
+        }
+    }
+}
+
+impl ToString for Threshold {
+    fn to_string(&self) -> String {
+        match self {
+            &Threshold::ZScore(z) => format!("z-score {}", z),
+            &Threshold::Percent(pct) => format!("{}%", pct)
+        }
+    }
+}
+
+/// Rate (queries per minute) and query time distribution of one fingerprint
+struct Profile {
+    count: usize,
+    total_time: f64,
+    rate_mean: f64,
+    rate_std: f64,
+    time_mean: f64,
+    time_std: f64,
+    p50: f64,
+    p95: f64,
+    p99: f64
+}
+
+fn mean_std(values: &Vec<f64>) -> (f64, f64) {
+    let mean = values.iter().sum::<f64>() / values.len().max(1) as f64;
+    let variance = values.iter().map(|value| (value - mean) * (value - mean)).sum::<f64>() / values.len().max(1) as f64;
+
+    (mean, variance.sqrt())
+}
+
+/// Per-fingerprint profiles of a known-good period
+pub struct Baseline {
+    minutes: i64,
+    profiles: HashMap<String, Profile>
+}
+
+impl Baseline {
+    /// Rates are counted per minute over the whole time span, minutes without
+    /// queries of a fingerprint included
+    pub fn build(qq: &Vec<&Query>) -> Self {
+        let minute = |q: &Query| q.timestamp / 60;
+        let timed: Vec<&Query> = qq.iter().cloned().filter(|q| q.timestamp >= 0).collect();
+        let first = timed.iter().map(|q| minute(q)).min().unwrap_or(0);
+        let last = timed.iter().map(|q| minute(q)).max().unwrap_or(0);
+        let minutes = last - first + 1;
+        let mut by_fingerprint: HashMap<&String, Vec<&Query>> = HashMap::new();
+
+        for q in timed.into_iter() {
+            by_fingerprint.entry(&q.fingerprint).or_insert_with(Vec::new).push(q);
+        }
+
+        let profiles = by_fingerprint.into_iter().map(|(fingerprint, queries)| {
+            let mut per_minute: BTreeMap<i64, f64> = BTreeMap::new();
+
+            for q in queries.iter() {
+                *per_minute.entry(minute(q)).or_insert(0.0) += 1.0;
+            }
+
+            let rates: Vec<f64> = (first..=last).map(|m| *per_minute.get(&m).unwrap_or(&0.0)).collect();
+            let mut times: Vec<f64> = queries.iter().filter(|q| q.query_time >= 0.0).map(|q| q.query_time).collect();
+            let (rate_mean, rate_std) = mean_std(&rates);
+            let (time_mean, time_std) = mean_std(&times);
+
+            (fingerprint.clone(), Profile {
+                count: queries.len(),
+                total_time: times.iter().sum(),
+                rate_mean,
+                rate_std,
+                time_mean,
+                time_std,
+                p50: stats::percentile(&mut times, 50.0),
+                p95: stats::percentile(&mut times, 95.0),
+                p99: stats::percentile(&mut times, 99.0)
+            })
+        }).collect();
+
+        Self {
+            minutes,
+            profiles
+        }
+    }
+
+    pub fn load(path: &String) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut baseline = Self {
+            minutes: 0,
+            profiles: HashMap::new()
+        };
+
+        for line in reader.lines() {
+            let line = line?;
+            let mut line_split = line.splitn(2, '=');
+            let key = line_split.next().unwrap_or("");
+            let value = line_split.next().unwrap_or("");
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid baseline line: {}", line));
+
+            match key {
+                "minutes" => baseline.minutes = value.parse().map_err(|_| invalid())?,
+                "fp" => {
+                    let fields: Vec<&str> = value.splitn(10, ' ').collect();
+
+                    if fields.len() < 10 {
+                        return Err(invalid());
+                    }
+
+                    let number = |index: usize| fields[index].parse::<f64>().map_err(|_| invalid());
+
+                    baseline.profiles.insert(fields[9].to_string(), Profile {
+                        count: fields[0].parse().map_err(|_| invalid())?,
+                        total_time: number(1)?,
+                        rate_mean: number(2)?,
+                        rate_std: number(3)?,
+                        time_mean: number(4)?,
+                        time_std: number(5)?,
+                        p50: number(6)?,
+                        p95: number(7)?,
+                        p99: number(8)?
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(baseline)
+    }
+
+    pub fn save(&self, path: &String) -> io::Result<()> {
+        let mut fingerprints: Vec<&String> = self.profiles.keys().collect();
+        let mut contents = format!("minutes={}\n", self.minutes);
+
+        fingerprints.sort();
+
+        for fingerprint in fingerprints {
+            let p = &self.profiles[fingerprint];
+
+            contents.push_str(&format!("fp={} {} {} {} {} {} {} {} {} {}\n",
+                                       p.count, p.total_time, p.rate_mean, p.rate_std, p.time_mean, p.time_std, p.p50, p.p95, p.p99, fingerprint));
+        }
+
+        state::write_atomic(path, &contents)
+    }
+}
+
+/// One metric of a fingerprint deviating from baseline
+struct Anomaly<'a> {
+    metric: &'static str,
+    baseline: f64,
+    current: f64,
+    z_score: f64,
+    change: f64,
+    fingerprint: &'a String
+}
+
+fn anomaly<'a>(metric: &'static str, baseline: f64, current: f64, std: f64, fingerprint: &'a String) -> Anomaly<'a> {
+    Anomaly {
+        metric,
+        baseline,
+        current,
+        z_score: (current - baseline) / std,
+        change: if baseline > 0.0 { (current - baseline) / baseline * 100.0 } else { 0.0 },
+        fingerprint
+    }
+}
+
+/// Fingerprints whose rate or p95 query time in current deviate from baseline,
+/// vanished ones included with zero rate
+pub fn report(current: &Baseline, cnf: &Config) -> String {
+    let baseline = match cnf.baseline {
+        Some(ref baseline) => baseline,
+        None => return String::new()
+    };
+
+    let score = |anomaly: &Anomaly| match cnf.anomaly {
+        Threshold::ZScore(_) => anomaly.z_score.abs(),
+        Threshold::Percent(_) => anomaly.change.abs()
+    };
+
+    let limit = match cnf.anomaly {
+        Threshold::ZScore(z) => z,
+        Threshold::Percent(pct) => pct
+    };
+
+    // Per-minute counts are close to Poisson, so variance is at least the mean
+    let rate_std = |base: &Profile| base.rate_std.max(base.rate_mean.sqrt()).max(::std::f64::MIN_POSITIVE);
+
+    let mut anomalies: Vec<Anomaly> = Vec::new();
+    let mut new_fingerprints: Vec<(&String, &Profile)> = Vec::new();
+
+    for (fingerprint, profile) in current.profiles.iter() {
+        let base = match baseline.profiles.get(fingerprint) {
+            Some(base) => base,
+            None => {
+                new_fingerprints.push((fingerprint, profile));
+                continue;
+            }
+        };
+
+        anomalies.push(anomaly("rate", base.rate_mean, profile.rate_mean, rate_std(base), fingerprint));
+        anomalies.push(anomaly("p95", base.p95, profile.p95, base.time_std.max(MIN_TIME_STD), fingerprint));
+    }
+
+    // Vanished fingerprints dropped to a zero rate
+    for (fingerprint, base) in baseline.profiles.iter() {
+        if !current.profiles.contains_key(fingerprint) {
+            anomalies.push(anomaly("rate", base.rate_mean, 0.0, rate_std(base), fingerprint));
+        }
+    }
+
+    anomalies.retain(|anomaly| score(anomaly) >= limit);
+    anomalies.sort_by(|lhs, rhs| score(rhs).partial_cmp(&score(lhs)).unwrap()
+        .then_with(|| lhs.fingerprint.cmp(rhs.fingerprint))
+        .then_with(|| lhs.metric.cmp(rhs.metric)));
+
+    let mut buf = format!("ANOMALY REPORT ({} or more, baseline of {} fingerprints over {} minutes):\n",
+                          cnf.anomaly.to_string(), baseline.profiles.len(), baseline.minutes);
+
+    if anomalies.len() == 0 {
+        buf.push_str("\tNone\n");
+    } else {
+        buf.push_str(&format!("{:<6} {:>14} {:>14} {:>10} {:>10} {}\n", "METRIC", "BASELINE", "CURRENT", "Z_SCORE", "CHANGE", "FINGERPRINT"));
+
+        for anomaly in anomalies.iter() {
+            buf.push_str(&format!("{:<6} {:>14.6} {:>14.6} {:>10.2} {:>9.1}% {}\n",
+                                  anomaly.metric, anomaly.baseline, anomaly.current, anomaly.z_score, anomaly.change, anomaly.fingerprint));
+        }
+    }
+
+    new_fingerprints.sort_by(|lhs, rhs| rhs.1.total_time.partial_cmp(&lhs.1.total_time).unwrap()
+        .then_with(|| lhs.0.cmp(rhs.0)));
+
+    buf.push_str("\nNew fingerprints:\n");
+
+    if new_fingerprints.len() == 0 {
+        buf.push_str("\tNone\n");
+        return buf;
+    }
+
+    buf.push_str(&format!("{:>10} {:>14} {:>12} {}\n", "COUNT", "TOTAL_TIME", "P95_TIME", "FINGERPRINT"));
+
+    for &(fingerprint, profile) in new_fingerprints.iter().take(NEW_SHOWN) {
+        buf.push_str(&format!("{:>10} {:>14.6} {:>12.6} {}\n", profile.count, profile.total_time, profile.p95, fingerprint));
+    }
+
+    buf
+}
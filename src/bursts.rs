@@ -0,0 +1,121 @@
+use types::{Query, Config};
+use std::collections::HashMap;
+
+/// Fingerprints shown
+const PATTERNS_SHOWN: usize = 20;
+
+/// Clients shown per fingerprint
+const CLIENTS_SHOWN: usize = 5;
+
+/// Repetitions of one fingerprint from one client found together
+struct Pattern {
+    bursts: usize,
+    queries: usize,
+    max_burst: usize,
+    total_time: f64,
+    clients: Vec<String>
+}
+
+impl Pattern {
+    fn new() -> Self {
+        Self {
+            bursts: 0,
+            queries: 0,
+            max_burst: 0,
+            total_time: 0.0,
+            clients: Vec::new()
+        }
+    }
+
+    fn add(&mut self, burst: &[&Query], client: &String) {
+        self.bursts += 1;
+        self.queries += burst.len();
+        self.max_burst = self.max_burst.max(burst.len());
+        self.total_time += burst.iter().map(|q| q.query_time.max(0.0)).sum::<f64>();
+
+        if !self.clients.contains(client) {
+            self.clients.push(client.clone());
+        }
+    }
+}
+
+/// Runs of queries each following the previous one within window, at least min long
+fn bursts<'a>(queries: &Vec<&'a Query>, min: usize, window: i64) -> Vec<Vec<&'a Query>> {
+    let mut bursts: Vec<Vec<&Query>> = Vec::new();
+    let mut run: Vec<&Query> = Vec::new();
+
+    for &q in queries.iter() {
+        let follows = run.last().map(|last| q.timestamp - last.timestamp <= window).unwrap_or(true);
+
+        if !follows {
+            if run.len() >= min {
+                bursts.push(run);
+            }
+
+            run = Vec::new();
+        }
+
+        run.push(q);
+    }
+
+    if run.len() >= min {
+        bursts.push(run);
+    }
+
+    bursts
+}
+
+/// Same fingerprint repeated by one user, host and connection in quick succession
+pub fn report(qq: &Vec<Query>, cnf: &Config) -> String {
+    let mut clients: HashMap<(&String, &String, &String, i64), Vec<&Query>> = HashMap::new();
+
+    for q in qq.iter().filter(|q| q.timestamp >= 0) {
+        clients.entry((&q.fingerprint, &q.user, &q.host, q.connection_id)).or_insert_with(Vec::new).push(q);
+    }
+
+    let mut patterns: HashMap<&String, Pattern> = HashMap::new();
+
+    for (&(fingerprint, user, host, connection_id), queries) in clients.iter_mut() {
+        queries.sort_by_key(|q| q.timestamp);
+
+        let client = if connection_id >= 0 {
+            format!("{}@{}#{}", user, host, connection_id)
+        } else {
+            format!("{}@{}", user, host)
+        };
+
+        for burst in bursts(queries, cnf.burst_min, cnf.burst_window) {
+            patterns.entry(fingerprint).or_insert_with(Pattern::new).add(&burst, &client);
+        }
+    }
+
+    let mut rows: Vec<(&String, Pattern)> = patterns.into_iter().collect();
+
+    rows.sort_by(|lhs, rhs| rhs.1.total_time.partial_cmp(&lhs.1.total_time).unwrap()
+        .then_with(|| lhs.0.cmp(rhs.0)));
+
+    let mut buf = format!("N+1 REPORT ({} or more repetitions, {}s apart at most):\n", cnf.burst_min, cnf.burst_window);
+
+    if rows.len() == 0 {
+        buf.push_str("\tNone\n");
+        return buf;
+    }
+
+    buf.push_str(&format!("{:>8} {:>10} {:>10} {:>14} {}\n", "BURSTS", "QUERIES", "MAX_BURST", "TOTAL_TIME", "FINGERPRINT"));
+
+    for &mut (fingerprint, ref mut pattern) in rows.iter_mut().take(PATTERNS_SHOWN) {
+        pattern.clients.sort();
+
+        buf.push_str(&format!("{:>8} {:>10} {:>10} {:>14.6} {}\n",
+                              pattern.bursts, pattern.queries, pattern.max_burst, pattern.total_time, fingerprint));
+        buf.push_str(&format!("\t\tCLIENTS: {}", pattern.clients.iter().take(CLIENTS_SHOWN).cloned().collect::<Vec<String>>().join(", ")));
+
+        if pattern.clients.len() > CLIENTS_SHOWN {
+            buf.push_str(&format!(" and {} more", pattern.clients.len() - CLIENTS_SHOWN));
+        }
+
+        buf.push_str("\n");
+    }
+
+    buf
+}
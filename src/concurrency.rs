@@ -0,0 +1,228 @@
+use types::{Query, Config};
+use std::collections::HashMap;
+
+/// Rows in concurrency over time chart
+const TIMELINE_BUCKETS: i64 = 60;
+
+/// Peak windows and lock pile-ups shown
+const WINDOWS_SHOWN: usize = 5;
+
+/// Queries waiting for locks at once that make a pile-up
+const PILEUP_MIN: usize = 2;
+
+/// Fingerprints shown per lock pile-up
+const PILEUP_FINGERPRINTS: usize = 5;
+
+/// Time a query spent in server
+struct Interval<'a> {
+    start: f64,
+    end: f64,
+    q: &'a Query
+}
+
+/// Running queries count changes: (time, count from this time on). Moments
+/// come from the same log values, so equal ones compare exactly
+#[cfg_attr(feature = "cargo-clippy", allow(float_cmp))]
+fn levels(intervals: &Vec<(f64, f64)>) -> Vec<(f64, usize)> {
+    let mut events: Vec<(f64, i64)> = Vec::with_capacity(intervals.len() * 2);
+
+    for &(start, end) in intervals.iter() {
+        events.push((start, 1));
+        events.push((end, -1));
+    }
+
+    events.sort_by(|lhs, rhs| lhs.0.partial_cmp(&rhs.0).unwrap());
+
+    let mut steps: Vec<(f64, usize)> = Vec::new();
+    let mut level: i64 = 0;
+
+    // All changes at one moment make one step, so queries ending at a moment
+    // are not counted as running together with ones starting at it
+    for (time, change) in events.into_iter() {
+        level += change;
+
+        if let Some(last) = steps.last_mut() {
+            if last.0 == time {
+                last.1 = level as usize;
+                continue;
+            }
+        }
+
+        steps.push((time, level as usize));
+    }
+
+    steps
+}
+
+/// Maximal time windows with at least threshold queries running: (from, to, peak)
+fn windows(steps: &Vec<(f64, usize)>, threshold: usize) -> Vec<(f64, f64, usize)> {
+    let mut windows: Vec<(f64, f64, usize)> = Vec::new();
+    let mut current: Option<(f64, usize)> = None;
+
+    for &(time, level) in steps.iter() {
+        current = match (current, level >= threshold) {
+            (None, true) => Some((time, level)),
+            (Some((from, peak)), true) => Some((from, peak.max(level))),
+            (Some((from, peak)), false) => {
+                windows.push((from, time, peak));
+                None
+            }
+            (None, false) => None
+        };
+    }
+
+    windows
+}
+
+fn overlapping<'a>(intervals: &'a Vec<Interval>, from: f64, to: f64) -> Vec<&'a Interval<'a>> {
+    intervals.iter().filter(|interval| interval.start < to && interval.end > from).collect()
+}
+
+fn format_time(time: f64, cnf: &Config) -> String {
+    cnf.format_timestamp(time.floor() as i64)
+}
+
+fn timeline(steps: &Vec<(f64, usize)>, cnf: &Config) -> String {
+    let first = steps[0].0.floor() as i64;
+    let last = steps[steps.len() - 1].0.ceil() as i64;
+    let width = ((last - first + TIMELINE_BUCKETS - 1) / TIMELINE_BUCKETS).max(1);
+    let mut buckets: Vec<usize> = vec![0; ((last - first) / width + 1) as usize];
+
+    for (index, &(time, level)) in steps.iter().enumerate() {
+        let until = steps.get(index + 1).map(|next| next.0).unwrap_or(time);
+        let first_bucket = ((time - first as f64) / width as f64).floor() as usize;
+        let last_bucket = ((until - first as f64) / width as f64).ceil() as usize;
+
+        for bucket in buckets.iter_mut().take(last_bucket.max(first_bucket + 1)).skip(first_bucket) {
+            *bucket = (*bucket).max(level);
+        }
+    }
+
+    let max_level = buckets.iter().cloned().max().unwrap_or(0);
+    let mut buf = format!("Concurrency over time (max running, {}s buckets):\n", width);
+
+    for (index, level) in buckets.iter().enumerate() {
+        let bar_len = if max_level > 0 { ((*level as f64 / max_level as f64) * 60.0).ceil() as usize } else { 0 };
+
+        buf.push_str(format!("\t{} {:>6} {}", cnf.format_timestamp(first + index as i64 * width), level,
+                             "#".repeat(bar_len)).trim_end());
+        buf.push_str("\n");
+    }
+
+    buf
+}
+
+fn peak_windows(steps: &Vec<(f64, usize)>, intervals: &Vec<Interval>, peak: usize, cnf: &Config) -> String {
+    let threshold = ((peak * 3 + 3) / 4).max(2);
+    let mut rows = windows(steps, threshold);
+
+    rows.sort_by(|lhs, rhs| rhs.2.cmp(&lhs.2)
+        .then_with(|| (rhs.1 - rhs.0).partial_cmp(&(lhs.1 - lhs.0)).unwrap())
+        .then_with(|| lhs.0.partial_cmp(&rhs.0).unwrap()));
+
+    let mut buf = format!("Peak windows ({} or more queries running):\n", threshold);
+
+    if rows.len() == 0 {
+        buf.push_str("\tNone\n");
+        return buf;
+    }
+
+    buf.push_str(&format!("\t{:<24} {:>10} {:>6} {:>8}\n", "FROM", "DURATION", "PEAK", "QUERIES"));
+
+    for &(from, to, peak) in rows.iter().take(WINDOWS_SHOWN) {
+        buf.push_str(&format!("\t{:<24} {:>10.3} {:>6} {:>8}\n",
+                              format_time(from, cnf), to - from, peak, overlapping(intervals, from, to).len()));
+    }
+
+    buf
+}
+
+/// Lock time of waits overlapping each window. Windows are disjoint and in time
+/// order, so waits ended before a window are never needed again
+fn windows_lock_time(waits: &Vec<(f64, f64, f64)>, windows: &Vec<(f64, f64, usize)>) -> Vec<f64> {
+    let mut next = 0;
+    let mut active: Vec<(f64, f64, f64)> = Vec::new();
+
+    windows.iter().map(|&(from, to, _)| {
+        while next < waits.len() && waits[next].0 < to {
+            active.push(waits[next]);
+            next += 1;
+        }
+
+        active.retain(|wait| wait.1 > from);
+        active.iter().map(|wait| wait.2).sum()
+    }).collect()
+}
+
+/// Windows with PILEUP_MIN or more queries waiting for locks at once
+fn lock_pileups(intervals: &Vec<Interval>, cnf: &Config) -> String {
+    let mut waits: Vec<(f64, f64, f64)> = intervals.iter()
+        .filter(|interval| interval.q.lock_time > 0.0)
+        .map(|interval| (interval.start, interval.start + interval.q.lock_time, interval.q.lock_time))
+        .collect();
+
+    waits.sort_by(|lhs, rhs| lhs.0.partial_cmp(&rhs.0).unwrap());
+
+    let found = windows(&levels(&waits.iter().map(|wait| (wait.0, wait.1)).collect()), PILEUP_MIN);
+    let mut rows: Vec<(f64, f64, usize, f64)> = found.iter().zip(windows_lock_time(&waits, &found).into_iter())
+        .map(|(&(from, to, peak), lock_time)| (from, to, peak, lock_time))
+        .collect();
+
+    rows.sort_by(|lhs, rhs| rhs.2.cmp(&lhs.2)
+        .then_with(|| rhs.3.partial_cmp(&lhs.3).unwrap())
+        .then_with(|| lhs.0.partial_cmp(&rhs.0).unwrap()));
+
+    let mut buf = format!("Lock pile-ups ({} or more queries waiting for locks):\n", PILEUP_MIN);
+
+    if rows.len() == 0 {
+        buf.push_str("\tNone\n");
+        return buf;
+    }
+
+    buf.push_str(&format!("\t{:<24} {:>10} {:>8} {:>14}\n", "FROM", "DURATION", "WAITING", "LOCK_TIME"));
+
+    for &(from, to, peak, lock_time) in rows.iter().take(WINDOWS_SHOWN) {
+        buf.push_str(&format!("\t{:<24} {:>10.3} {:>8} {:>14.6}\n",
+                              format_time(from, cnf), to - from, peak, lock_time));
+
+        let mut fingerprints: HashMap<&String, usize> = HashMap::new();
+
+        for interval in overlapping(intervals, from, to) {
+            *fingerprints.entry(&interval.q.fingerprint).or_insert(0) += 1;
+        }
+
+        let mut fingerprints: Vec<(&String, usize)> = fingerprints.into_iter().collect();
+
+        fingerprints.sort_by(|lhs, rhs| rhs.1.cmp(&lhs.1).then_with(|| lhs.0.cmp(rhs.0)));
+
+        for &(fingerprint, count) in fingerprints.iter().take(PILEUP_FINGERPRINTS) {
+            buf.push_str(&format!("\t\t{:>6} {}\n", count, fingerprint));
+        }
+    }
+
+    buf
+}
+
+pub fn report(qq: &Vec<Query>, cnf: &Config) -> String {
+    let intervals: Vec<Interval> = qq.iter()
+        .filter(|q| q.timestamp >= 0 && q.query_time >= 0.0)
+        .map(|q| Interval { start: q.start(), end: q.timestamp as f64, q })
+        .collect();
+
+    let mut buf = "CONCURRENCY REPORT:\n".to_string();
+
+    if intervals.len() == 0 {
+        buf.push_str("\tNo queries with timestamp and query time\n");
+        return buf;
+    }
+
+    let steps = levels(&intervals.iter().map(|interval| (interval.start, interval.end)).collect());
+    let &(peak_time, peak) = steps.iter().max_by(|lhs, rhs| lhs.1.cmp(&rhs.1).then_with(|| rhs.0.partial_cmp(&lhs.0).unwrap())).unwrap();
+
+    buf.push_str(&format!("\tPeak concurrency: {} at {}\n\n", peak, format_time(peak_time, cnf)));
+    buf.push_str(&timeline(&steps, cnf));
+    buf.push_str(&peak_windows(&steps, &intervals, peak, cnf));
+    buf.push_str(&lock_pileups(&intervals, cnf));
+
+    buf
+}
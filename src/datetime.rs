@@ -0,0 +1,180 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use chrono::prelude::Utc;
+use chrono::format::{StrftimeItems, Item};
+use chrono_tz::Tz;
+use regex::Regex;
+
+#[derive(Clone)]
+pub enum Zone {
+    Fixed(FixedOffset),
+    Named(Tz)
+}
+
+impl Zone {
+    pub fn utc() -> Self {
+        Zone::Fixed(FixedOffset::east(0))
+    }
+
+    /// Accepts IANA names like Europe/Moscow, UTC, Z, +HH, +HH:MM and +HHMM (with + or -)
+    pub fn parse(zone: &str) -> Option<Zone> {
+        lazy_static! {
+            static ref regex: Regex = Regex::new(r"^(?:UTC|GMT)?(?P<sign>[+-])(?P<hh>\d{1,2})(?::?(?P<mm>\d{2}))?$").unwrap();
+        }
+
+        let zone = zone.trim();
+
+        if zone.eq_ignore_ascii_case("UTC") || zone.eq_ignore_ascii_case("GMT") || zone == "Z" {
+            return Some(Zone::utc());
+        }
+
+        if let Some(capts) = regex.captures(zone) {
+            let hours = capts["hh"].parse::<i32>().unwrap();
+            let minutes = capts.name("mm").map(|mm| mm.as_str().parse::<i32>().unwrap()).unwrap_or(0);
+            let seconds = (hours * 3600 + minutes * 60) * if &capts["sign"] == "-" { -1 } else { 1 };
+
+            if hours < 24 && minutes < 60 {
+                return Some(Zone::Fixed(FixedOffset::east(seconds)));
+            }
+        }
+
+        zone.parse::<Tz>().ok().map(Zone::Named)
+    }
+
+    pub fn local_timestamp(&self, date_time: &NaiveDateTime) -> Option<i64> {
+        match self {
+            &Zone::Fixed(ref offset) => offset.from_local_datetime(date_time).single().map(|dt| dt.timestamp()),
+            &Zone::Named(ref tz) => tz.from_local_datetime(date_time).earliest().map(|dt| dt.timestamp())
+        }
+    }
+
+    pub fn format(&self, timestamp: i64, format: &str) -> String {
+        match self {
+            &Zone::Fixed(ref offset) => offset.timestamp(timestamp, 0).format(format).to_string(),
+            &Zone::Named(ref tz) => tz.timestamp(timestamp, 0).format(format).to_string()
+        }
+    }
+
+    pub fn hour(&self, timestamp: i64) -> u32 {
+        match self {
+            &Zone::Fixed(ref offset) => offset.timestamp(timestamp, 0).hour(),
+            &Zone::Named(ref tz) => tz.timestamp(timestamp, 0).hour()
+        }
+    }
+}
+
+impl ToString for Zone {
+    fn to_string(&self) -> String {
+        match self {
+            &Zone::Fixed(ref offset) => offset.to_string(),
+            &Zone::Named(ref tz) => tz.name().to_string()
+        }
+    }
+}
+
+/// Parses Unix timestamp, DD/MM/YYYY[ HH:MM[:SS]], YYYY-MM-DD[ HH:MM[:SS]]
+/// and RFC3339. Dates without time are the beginning of the day,
+/// or its last second when end_of_day is set.
+pub fn parse_timestamp(value: &str, zone: &Zone, end_of_day: bool) -> Option<i64> {
+    lazy_static! {
+        static ref dmy_regex: Regex = Regex::new(r"^(?P<dd>\d{2})[/\-.](?P<mm>\d{2})[/\-.](?P<yyyy>\d{4})(?:[ T:](?P<time>\d{2}:\d{2}(?::\d{2})?))?$").unwrap();
+    }
+
+    lazy_static! {
+        static ref ymd_regex: Regex = Regex::new(r"^(?P<yyyy>\d{4})-(?P<mm>\d{2})-(?P<dd>\d{2})(?:[ T](?P<time>\d{2}:\d{2}(?::\d{2})?))?$").unwrap();
+    }
+
+    let value = value.trim();
+
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Some(timestamp);
+    }
+
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Some(date_time.timestamp());
+    }
+
+    let capts = match dmy_regex.captures(value).or_else(|| ymd_regex.captures(value)) {
+        Some(capts) => capts,
+        None => return None
+    };
+
+    let date = match NaiveDate::from_ymd_opt(capts["yyyy"].parse::<i32>().unwrap(),
+                                             capts["mm"].parse::<u32>().unwrap(),
+                                             capts["dd"].parse::<u32>().unwrap()) {
+        Some(date) => date,
+        None => return None
+    };
+
+    let date_time = match capts.name("time") {
+        Some(time) => {
+            let time = time.as_str();
+            let format = if time.len() > 5 { "%H:%M:%S" } else { "%H:%M" };
+
+            match NaiveDateTime::parse_from_str(&format!("{} {}", date, time), &format!("%Y-%m-%d {}", format)) {
+                Ok(date_time) => date_time,
+                Err(_) => return None
+            }
+        }
+        None => if end_of_day { date.and_hms(23, 59, 59) } else { date.and_hms(0, 0, 0) }
+    };
+
+    zone.local_timestamp(&date_time)
+}
+
+/// Parses durations like 90, 90s, 30m, 2h, 1d, 1w or combined 1h30m to seconds,
+/// None when invalid or too long to fit
+pub fn parse_duration(value: &str) -> Option<i64> {
+    lazy_static! {
+        static ref full_regex: Regex = Regex::new(r"^(\d+[smhdw])+$").unwrap();
+    }
+
+    lazy_static! {
+        static ref part_regex: Regex = Regex::new(r"(?P<num>\d+)(?P<unit>[smhdw])").unwrap();
+    }
+
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<i64>() {
+        return if seconds >= 0 { Some(seconds) } else { None };
+    }
+
+    if !full_regex.is_match(value) {
+        return None;
+    }
+
+    let mut seconds: i64 = 0;
+
+    for capts in part_regex.captures_iter(value) {
+        let num = match capts["num"].parse::<i64>() {
+            Ok(num) => num,
+            Err(_) => return None
+        };
+
+        let unit: i64 = match &capts["unit"] {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            _   => 604_800
+        };
+
+        // Too long durations are invalid rather than wrapped around
+        seconds = match num.checked_mul(unit).and_then(|part| seconds.checked_add(part)) {
+            Some(seconds) => seconds,
+            None => return None
+        };
+    }
+
+    Some(seconds)
+}
+
+pub fn valid_format(format: &str) -> bool {
+    StrftimeItems::new(format).all(|item| match item {
+        Item::Error => false,
+        _ => true
+    })
+}
+
+pub fn now() -> i64 {
+    Utc::now().timestamp()
+}
@@ -0,0 +1,663 @@
+use types::{Query, EXTENDED_FIELDS};
+use regex::Regex;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+    End
+}
+
+impl ToString for Token {
+    fn to_string(&self) -> String {
+        match self {
+            &Token::Number(ref number) => number.to_string(),
+            &Token::Str(ref string) => format!("'{}'", string),
+            &Token::Ident(ref ident) => ident.clone(),
+            &Token::Op(op) => op.to_string(),
+            &Token::LParen => "(".to_string(),
+            &Token::RParen => ")".to_string(),
+            &Token::Comma => ",".to_string(),
+            &Token::End => "end of expression".to_string()
+        }
+    }
+}
+
+const OPERATORS: &'static [&'static str] = &[
+    "==", "!=", "<>", "<=", ">=", "=~", "!~", "&&", "||",
+    "=", "<", ">", "+", "-", "*", "/", "%", "!"
+];
+
+#[derive(Clone, Debug)]
+enum Field {
+    Timestamp,
+    QueryTime,
+    LockTime,
+    RowsSent,
+    RowsExamined,
+    RowsAffected,
+    Db,
+    User,
+    Host,
+    Ip,
+    Query,
+    RawQuery,
+    Fingerprint,
+    Statement,
+    Table,
+    Tag(String),
+    Extended(String)
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        let lower = name.to_lowercase();
+
+        match &*lower {
+            "ts" | "timestamp" => Some(Field::Timestamp),
+            "qt" | "query_time" => Some(Field::QueryTime),
+            "lt" | "lock_time" => Some(Field::LockTime),
+            "rs" | "rows_sent" => Some(Field::RowsSent),
+            "re" | "rows_examined" => Some(Field::RowsExamined),
+            "ra" | "rows_affected" => Some(Field::RowsAffected),
+            "db" | "database" => Some(Field::Db),
+            "user" => Some(Field::User),
+            "host" => Some(Field::Host),
+            "ip" => Some(Field::Ip),
+            "query" => Some(Field::Query),
+            "raw" | "raw_query" => Some(Field::RawQuery),
+            "fp" | "fingerprint" => Some(Field::Fingerprint),
+            "stmt" | "statement" => Some(Field::Statement),
+            "table" => Some(Field::Table),
+            _ if (lower.starts_with("tag:") || lower.starts_with("tag.")) && name.len() > 4 => Some(Field::Tag(name[4..].to_string())),
+            _ if EXTENDED_FIELDS.contains(&&*lower) => Some(Field::Extended(lower)),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Num(f64),
+    Str(String),
+    List(Vec<String>),
+    Bool(bool),
+    Null
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            &Value::Num(number) => number != 0.0,
+            &Value::Str(ref string) => string.len() > 0,
+            &Value::List(ref list) => list.len() > 0,
+            &Value::Bool(boolean) => boolean,
+            &Value::Null => false
+        }
+    }
+
+    fn strings(&self) -> Vec<&String> {
+        match self {
+            &Value::Str(ref string) => vec![string],
+            &Value::List(ref list) => list.iter().collect(),
+            _ => Vec::new()
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Literal(Value),
+    Field(Field),
+    Neg(Box<Node>),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Arith(&'static str, Box<Node>, Box<Node>),
+    Cmp(&'static str, Box<Node>, Box<Node>),
+    Match(Box<Node>, Regex, bool),
+    In(Box<Node>, Vec<Value>, bool)
+}
+
+/// Filter expression compiled once from `--where` and evaluated per query.
+#[derive(Clone, Debug)]
+pub struct Expr {
+    source: String,
+    root: Node
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    index: usize
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<(Token, usize)> = Vec::new();
+    let mut index = 0;
+
+    'outer: while index < chars.len() {
+        let c = chars[index];
+        let position = index + 1;
+
+        if c.is_whitespace() {
+            index += 1;
+        } else if c == '(' {
+            tokens.push((Token::LParen, position));
+            index += 1;
+        } else if c == ')' {
+            tokens.push((Token::RParen, position));
+            index += 1;
+        } else if c == ',' {
+            tokens.push((Token::Comma, position));
+            index += 1;
+        } else if c == '\'' || c == '"' {
+            let mut string = String::new();
+            index += 1;
+
+            while index < chars.len() && chars[index] != c {
+                if chars[index] == '\\' && index + 1 < chars.len() {
+                    index += 1;
+
+                    if chars[index] != c && chars[index] != '\\' {
+                        string.push('\\');
+                    }
+                }
+
+                string.push(chars[index]);
+                index += 1;
+            }
+
+            if index >= chars.len() {
+                return Err(format!("Unterminated string at position {}", position));
+            }
+
+            tokens.push((Token::Str(string), position));
+            index += 1;
+        } else if c.is_digit(10) || (c == '.' && index + 1 < chars.len() && chars[index + 1].is_digit(10)) {
+            let start = index;
+
+            while index < chars.len() && (chars[index].is_digit(10) || chars[index] == '.') {
+                index += 1;
+            }
+
+            let number: String = chars[start..index].iter().collect();
+
+            match number.parse::<f64>() {
+                Ok(number) => tokens.push((Token::Number(number), position)),
+                Err(_) => return Err(format!("Invalid number '{}' at position {}", number, position))
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let start = index;
+
+            // tag:KEY is spelled as in --group_by and --sort
+            while index < chars.len() && (chars[index].is_alphanumeric() || chars[index] == '_' || chars[index] == '.' ||
+                (chars[index] == ':' && chars[start..index].iter().collect::<String>().eq_ignore_ascii_case("tag"))) {
+                index += 1;
+            }
+
+            tokens.push((Token::Ident(chars[start..index].iter().collect()), position));
+        } else {
+            for op in OPERATORS.iter() {
+                let op_chars: Vec<char> = op.chars().collect();
+
+                if chars[index..].starts_with(&op_chars) {
+                    tokens.push((Token::Op(op), position));
+                    index += op_chars.len();
+                    continue 'outer;
+                }
+            }
+
+            return Err(format!("Unexpected character '{}' at position {}", c, position));
+        }
+    }
+
+    tokens.push((Token::End, chars.len() + 1));
+
+    Ok(tokens)
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.index].0
+    }
+
+    fn position(&self) -> usize {
+        self.tokens[self.index].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.index].0.clone();
+
+        if self.index + 1 < self.tokens.len() {
+            self.index += 1;
+        }
+
+        token
+    }
+
+    fn unexpected(&self) -> String {
+        format!("Unexpected {} at position {}", self.peek().to_string(), self.position())
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            &Token::Ident(ref ident) => ident.eq_ignore_ascii_case(keyword),
+            _ => false
+        }
+    }
+
+    fn is_op(&self, op: &str) -> bool {
+        match self.peek() {
+            &Token::Op(token_op) => token_op == op,
+            _ => false
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut lhs = self.and()?;
+
+        while self.is_keyword("OR") || self.is_op("||") {
+            self.next();
+            let rhs = self.and()?;
+            lhs = Node::Or(Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut lhs = self.not()?;
+
+        while self.is_keyword("AND") || self.is_op("&&") {
+            self.next();
+            let rhs = self.not()?;
+            lhs = Node::And(Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn not(&mut self) -> Result<Node, String> {
+        if self.is_keyword("NOT") || self.is_op("!") {
+            self.next();
+            let node = self.not()?;
+
+            return Ok(Node::Not(Box::new(node)));
+        }
+
+        self.cmp()
+    }
+
+    fn cmp(&mut self) -> Result<Node, String> {
+        let lhs = self.add()?;
+
+        let op = match self.peek() {
+            &Token::Op(op) if ["=", "==", "!=", "<>", "<", "<=", ">", ">="].contains(&op) => Some(op),
+            _ => None
+        };
+
+        if let Some(op) = op {
+            self.next();
+            let rhs = self.add()?;
+            let op = match op { "==" => "=", "<>" => "!=", _ => op };
+
+            return Ok(Node::Cmp(op, Box::new(lhs), Box::new(rhs)));
+        }
+
+        if self.is_op("=~") || self.is_op("!~") {
+            let negate = self.is_op("!~");
+            self.next();
+            let position = self.position();
+
+            return match self.next() {
+                Token::Str(pattern) => match Regex::new(&pattern) {
+                    Ok(regex) => Ok(Node::Match(Box::new(lhs), regex, negate)),
+                    Err(err) => Err(format!("Invalid regex at position {}: {}", position, err))
+                },
+                _ => Err(format!("Expected regex string at position {}", position))
+            };
+        }
+
+        let negate = self.is_keyword("NOT");
+
+        if negate {
+            self.next();
+
+            if !self.is_keyword("IN") {
+                return Err(format!("Expected IN at position {}", self.position()));
+            }
+        }
+
+        if self.is_keyword("IN") {
+            self.next();
+
+            if *self.peek() != Token::LParen {
+                return Err(format!("Expected '(' at position {}", self.position()));
+            }
+
+            self.next();
+            let mut values: Vec<Value> = Vec::new();
+
+            loop {
+                let negative = self.is_op("-");
+
+                if negative {
+                    self.next();
+                }
+
+                let value = match self.peek() {
+                    &Token::Number(number) => Value::Num(if negative { -number } else { number }),
+                    &Token::Str(ref string) if !negative => Value::Str(string.clone()),
+                    _ => return Err(format!("Expected literal in IN list at position {}", self.position()))
+                };
+
+                values.push(value);
+                self.next();
+
+                match self.peek() {
+                    &Token::Comma => {},
+                    &Token::RParen => break,
+                    _ => return Err(self.unexpected())
+                }
+
+                self.next();
+            }
+
+            self.next();
+
+            return Ok(Node::In(Box::new(lhs), values, negate));
+        }
+
+        Ok(lhs)
+    }
+
+    fn add(&mut self) -> Result<Node, String> {
+        let mut lhs = self.mul()?;
+
+        while self.is_op("+") || self.is_op("-") {
+            let op = if self.is_op("+") { "+" } else { "-" };
+            self.next();
+            let rhs = self.mul()?;
+            lhs = Node::Arith(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn mul(&mut self) -> Result<Node, String> {
+        let mut lhs = self.unary()?;
+
+        while self.is_op("*") || self.is_op("/") || self.is_op("%") {
+            let op = if self.is_op("*") { "*" } else if self.is_op("/") { "/" } else { "%" };
+            self.next();
+            let rhs = self.unary()?;
+            lhs = Node::Arith(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.is_op("-") {
+            self.next();
+            let node = self.unary()?;
+
+            return Ok(Node::Neg(Box::new(node)));
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let position = self.position();
+
+        match self.peek().clone() {
+            Token::Number(number) => {
+                self.next();
+                Ok(Node::Literal(Value::Num(number)))
+            }
+            Token::Str(string) => {
+                self.next();
+                Ok(Node::Literal(Value::Str(string)))
+            }
+            Token::LParen => {
+                self.next();
+                let node = self.or()?;
+
+                if *self.peek() != Token::RParen {
+                    return Err(format!("Expected ')' at position {}", self.position()));
+                }
+
+                self.next();
+                Ok(node)
+            }
+            Token::Ident(ident) => {
+                self.next();
+
+                if ident.eq_ignore_ascii_case("TRUE") {
+                    Ok(Node::Literal(Value::Bool(true)))
+                } else if ident.eq_ignore_ascii_case("FALSE") {
+                    Ok(Node::Literal(Value::Bool(false)))
+                } else if let Some(field) = Field::from_name(&ident) {
+                    Ok(Node::Field(field))
+                } else {
+                    Err(format!("Unknown field '{}' at position {}", ident, position))
+                }
+            }
+            _ => Err(self.unexpected())
+        }
+    }
+}
+
+fn field_value(field: &Field, q: &Query) -> Value {
+    match field {
+        &Field::Timestamp => if q.timestamp >= 0 { Value::Num(q.timestamp as f64) } else { Value::Null },
+        &Field::QueryTime => if q.query_time >= 0.0 { Value::Num(q.query_time) } else { Value::Null },
+        &Field::LockTime => if q.lock_time >= 0.0 { Value::Num(q.lock_time) } else { Value::Null },
+        &Field::RowsSent => if q.rows_sent >= 0 { Value::Num(q.rows_sent as f64) } else { Value::Null },
+        &Field::RowsExamined => if q.rows_examined >= 0 { Value::Num(q.rows_examined as f64) } else { Value::Null },
+        &Field::RowsAffected => if q.rows_affected >= 0 { Value::Num(q.rows_affected as f64) } else { Value::Null },
+        &Field::Db => Value::Str(q.db.clone()),
+        &Field::User => Value::Str(q.user.clone()),
+        &Field::Host => Value::Str(q.host.clone()),
+        &Field::Ip => Value::Str(q.ip.clone()),
+        &Field::Query => Value::Str(q.query.clone()),
+        &Field::RawQuery => Value::Str(q.raw_query.clone()),
+        &Field::Fingerprint => Value::Str(q.fingerprint.clone()),
+        &Field::Statement => Value::Str(q.statement.clone()),
+        &Field::Table => Value::List(q.tables.clone()),
+        &Field::Tag(ref key) => q.tags.get(key).map(|value| Value::Str(value.clone())).unwrap_or(Value::Null),
+        &Field::Extended(ref name) => match (q.extended_value(name), q.extended.get(name)) {
+            (Some(number), _) => Value::Num(number),
+            (None, Some(value)) => Value::Str(value.clone()),
+            (None, None) => Value::Null
+        }
+    }
+}
+
+fn compare(op: &str, lhs: &Value, rhs: &Value) -> bool {
+    let ordering = match (lhs, rhs) {
+        (&Value::Num(lhs), &Value::Num(rhs)) => lhs.partial_cmp(&rhs),
+        (&Value::Bool(lhs), &Value::Bool(rhs)) => lhs.partial_cmp(&rhs),
+        (&Value::List(ref list), &Value::Str(_)) => {
+            let eq_op = if op == "!=" { "=" } else { op };
+            let found = list.iter().any(|item| compare(eq_op, &Value::Str(item.clone()), rhs));
+
+            return if op == "!=" { !found } else { found };
+        }
+        (&Value::Str(ref lhs), &Value::Str(ref rhs)) => lhs.partial_cmp(rhs),
+        _ => None
+    };
+
+    match ordering {
+        Some(ordering) => match op {
+            "=" => ordering == ::std::cmp::Ordering::Equal,
+            "!=" => ordering != ::std::cmp::Ordering::Equal,
+            "<" => ordering == ::std::cmp::Ordering::Less,
+            "<=" => ordering != ::std::cmp::Ordering::Greater,
+            ">" => ordering == ::std::cmp::Ordering::Greater,
+            ">=" => ordering != ::std::cmp::Ordering::Less,
+            _ => false
+        },
+        None => false
+    }
+}
+
+fn eval(node: &Node, q: &Query) -> Value {
+    match node {
+        &Node::Literal(ref value) => value.clone(),
+        &Node::Field(ref field) => field_value(field, q),
+        &Node::Neg(ref node) => match eval(node, q) {
+            Value::Num(number) => Value::Num(-number),
+            _ => Value::Null
+        },
+        &Node::Not(ref node) => Value::Bool(!eval(node, q).truthy()),
+        &Node::And(ref lhs, ref rhs) => Value::Bool(eval(lhs, q).truthy() && eval(rhs, q).truthy()),
+        &Node::Or(ref lhs, ref rhs) => Value::Bool(eval(lhs, q).truthy() || eval(rhs, q).truthy()),
+        &Node::Arith(op, ref lhs, ref rhs) => match (eval(lhs, q), eval(rhs, q)) {
+            (Value::Num(lhs), Value::Num(rhs)) => match op {
+                "+" => Value::Num(lhs + rhs),
+                "-" => Value::Num(lhs - rhs),
+                "*" => Value::Num(lhs * rhs),
+                "/" if rhs != 0.0 => Value::Num(lhs / rhs),
+                "%" if rhs != 0.0 => Value::Num(lhs % rhs),
+                _ => Value::Null
+            },
+            _ => Value::Null
+        },
+        &Node::Cmp(op, ref lhs, ref rhs) => Value::Bool(compare(op, &eval(lhs, q), &eval(rhs, q))),
+        &Node::Match(ref node, ref regex, negate) => {
+            let value = eval(node, q);
+            let found = value.strings().iter().any(|string| regex.is_match(string));
+
+            Value::Bool(found != negate)
+        }
+        &Node::In(ref node, ref values, negate) => {
+            let value = eval(node, q);
+
+            if value == Value::Null {
+                return Value::Bool(false);
+            }
+
+            let found = values.iter().any(|item| compare("=", &value, item));
+
+            Value::Bool(found != negate)
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            index: 0
+        };
+
+        let root = parser.or()?;
+
+        if *parser.peek() != Token::End {
+            return Err(parser.unexpected());
+        }
+
+        Ok(Expr {
+            source: source.to_string(),
+            root
+        })
+    }
+
+    pub fn matches(&self, q: &Query) -> bool {
+        eval(&self.root, q).truthy()
+    }
+}
+
+impl ToString for Expr {
+    fn to_string(&self) -> String {
+        self.source.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query() -> Query {
+        let mut q = Query::new();
+
+        q.query_time = 2.5;
+        q.rows_sent = 10;
+        q.rows_examined = 5000;
+        q.user = "app".to_string();
+        q.query = "SELECT * FROM users WHERE id = 1".to_string();
+        q.statement = "SELECT".to_string();
+        q.tables = vec!["users".to_string()];
+        q.tags.insert("controller".to_string(), "Users#show".to_string());
+        q
+    }
+
+    fn eval_str(source: &str) -> bool {
+        Expr::parse(source).unwrap().matches(&query())
+    }
+
+    #[test]
+    fn precedence() {
+        assert!(eval_str("1 + 2 * 3 = 7"));
+        assert!(eval_str("(1 + 2) * 3 = 9"));
+        assert!(eval_str("10 - 2 - 3 = 5"));
+        assert!(eval_str("-2 * 3 = -6"));
+        assert!(eval_str("qt > 10 OR re > 1000 AND rs < 100"));
+        assert!(!eval_str("(qt > 10 OR re > 1000) AND rs > 100"));
+        assert!(eval_str("NOT qt > 10 AND re > 1000"));
+        assert!(!eval_str("NOT (qt > 1 AND re > 1000)"));
+        assert!(eval_str("re > 100 * rs && !(lt > qt / 2) || false"));
+    }
+
+    #[test]
+    fn quoting() {
+        assert!(eval_str("user = 'app'"));
+        assert!(eval_str("user = \"app\""));
+        assert!(eval_str("'it\\'s' = \"it's\""));
+        assert!(eval_str("stmt IN ('INSERT', 'SELECT') AND stmt NOT IN ('UPDATE')"));
+        assert!(eval_str("re IN (-1, 5000)"));
+        assert_eq!(Expr::parse("user = 'app").unwrap_err(), "Unterminated string at position 8");
+    }
+
+    #[test]
+    fn regex() {
+        assert!(eval_str("query =~ 'WHERE\\s+id\\s*='"));
+        assert!(eval_str("query !~ '(?i)^update'"));
+        assert!(eval_str("table =~ '^user'"));
+        assert!(!eval_str("table !~ '^user'"));
+        assert!(Expr::parse("query =~ '('").unwrap_err().starts_with("Invalid regex at position 10"));
+        assert_eq!(Expr::parse("query =~ 1").unwrap_err(), "Expected regex string at position 10");
+    }
+
+    #[test]
+    fn tags() {
+        assert!(eval_str("tag:controller = 'Users#show'"));
+        assert!(eval_str("tag.controller = 'Users#show'"));
+        assert!(eval_str("TAG:controller =~ '^Users'"));
+        assert_eq!(Expr::parse("tags:controller = 'x'").unwrap_err(), "Unexpected character ':' at position 5");
+    }
+
+    #[test]
+    fn missing_fields() {
+        // Missing values never compare, so both a condition and its opposite are false
+        assert!(!eval_str("lt < 1"));
+        assert!(!eval_str("lt >= 1"));
+        assert!(eval_str("NOT lt < 1"));
+        assert!(!eval_str("tag:action = 'show'"));
+        assert!(!eval_str("tag:action != 'show'"));
+        assert!(!eval_str("tag:action IN ('show')"));
+        assert!(!eval_str("tag:action NOT IN ('show')"));
+        assert!(!eval_str("lt + 1 > 0"));
+        assert!(!eval_str("qt / 0 > 0"));
+        assert!(!eval_str("read_rnd_next > 0"));
+        assert_eq!(Expr::parse("nope > 1").unwrap_err(), "Unknown field 'nope' at position 1");
+    }
+}
@@ -0,0 +1,124 @@
+use types::{Query, Config};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::collections::{HashMap, HashSet};
+
+/// Fingerprints listed per breached check
+const FINGERPRINTS_SHOWN: usize = 20;
+
+/// Fingerprints allowed by --fail_if_new_fingerprint, one per line.
+/// Lines are fingerprinted like logged queries, so queries may be listed as well.
+/// Empty lines and lines starting with # are skipped.
+pub fn load_allowlist(path: &String) -> io::Result<HashSet<String>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut allowlist: HashSet<String> = HashSet::new();
+
+    for line in reader.lines() {
+        let line = line?.trim().to_string();
+
+        if line.len() == 0 || line.starts_with('#') {
+            continue;
+        }
+
+        allowlist.insert(Query::fingerprint_of(&line));
+    }
+
+    Ok(allowlist)
+}
+
+/// Count, total and max query time of fingerprint
+struct Usage {
+    count: usize,
+    total_time: f64,
+    max_time: f64
+}
+
+fn usage<'a, I>(qq: I) -> Vec<(&'a String, Usage)> where I: Iterator<Item = &'a &'a Query> {
+    let mut fingerprints: HashMap<&String, Usage> = HashMap::new();
+
+    for q in qq {
+        let usage = fingerprints.entry(&q.fingerprint).or_insert(Usage { count: 0, total_time: 0.0, max_time: 0.0 });
+
+        usage.count += 1;
+        usage.total_time += q.query_time.max(0.0);
+        usage.max_time = usage.max_time.max(q.query_time);
+    }
+
+    let mut rows: Vec<(&String, Usage)> = fingerprints.into_iter().collect();
+
+    rows.sort_by(|lhs, rhs| rhs.1.total_time.partial_cmp(&lhs.1.total_time).unwrap()
+        .then_with(|| lhs.0.cmp(rhs.0)));
+
+    rows
+}
+
+fn check(buf: &mut String, title: &str, rows: &Vec<(&String, Usage)>) -> bool {
+    if rows.len() == 0 {
+        buf.push_str(&format!("\t{}: passed\n", title));
+        return false;
+    }
+
+    buf.push_str(&format!("\t{}: FAILED, {} queries of {} fingerprints\n", title,
+                          rows.iter().map(|row| row.1.count).sum::<usize>(), rows.len()));
+    buf.push_str(&format!("\t{:>10} {:>14} {:>12} {}\n", "COUNT", "TOTAL_TIME", "MAX_TIME", "FINGERPRINT"));
+
+    for &(fingerprint, ref usage) in rows.iter().take(FINGERPRINTS_SHOWN) {
+        buf.push_str(&format!("\t{:>10} {:>14.6} {:>12.6} {}\n", usage.count, usage.total_time, usage.max_time, fingerprint));
+    }
+
+    if rows.len() > FINGERPRINTS_SHOWN {
+        buf.push_str(&format!("\t... {} more\n", rows.len() - FINGERPRINTS_SHOWN));
+    }
+
+    true
+}
+
+/// Result of CI gate checks over queries passing filters
+pub struct Verdict {
+    pub breached: usize,
+    report: String
+}
+
+impl Verdict {
+    pub fn new(qq: &Vec<&Query>, cnf: &Config) -> Self {
+        let mut report = "GATE REPORT:\n".to_string();
+        let mut breached = 0;
+
+        for fail_if in cnf.fail_if.iter() {
+            let rows = usage(qq.iter().filter(|q| fail_if.matches(q)));
+
+            if check(&mut report, &format!("--fail_if \"{}\"", fail_if.to_string()), &rows) {
+                breached += 1;
+            }
+        }
+
+        if let Some(ref allowlist) = cnf.allowlist {
+            let rows = usage(qq.iter().filter(|q| !allowlist.contains(&q.fingerprint)));
+
+            if check(&mut report, &format!("--fail_if_new_fingerprint \"{}\"", cnf.allowlist_file), &rows) {
+                breached += 1;
+            }
+        }
+
+        if cnf.max_fp_time >= 0.0 {
+            let rows = usage(qq.iter()).into_iter().filter(|row| row.1.total_time > cnf.max_fp_time).collect();
+
+            if check(&mut report, &format!("--max_fp_time {}", cnf.max_fp_time), &rows) {
+                breached += 1;
+            }
+        }
+
+        report.push_str(&format!("\nGATE: {}\n", if breached > 0 { format!("FAILED ({} checks)", breached) } else { "PASSED".to_string() }));
+
+        Self {
+            breached,
+            report
+        }
+    }
+}
+
+impl ToString for Verdict {
+    fn to_string(&self) -> String {
+        self.report.clone()
+    }
+}
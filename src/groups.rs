@@ -0,0 +1,130 @@
+use types::{Query, Config, GroupDim};
+use datetime::Zone;
+use stats;
+use std::collections::HashMap;
+
+struct GroupStat {
+    count: usize,
+    total_time: f64,
+    rows_examined: i64,
+    times: Vec<f64>
+}
+
+impl GroupStat {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            total_time: 0.0,
+            rows_examined: 0,
+            times: Vec::new()
+        }
+    }
+
+    fn add(&mut self, q: &Query) {
+        self.count += 1;
+
+        if q.query_time >= 0.0 {
+            self.total_time += q.query_time;
+            self.times.push(q.query_time);
+        }
+
+        if q.rows_examined >= 0 {
+            self.rows_examined += q.rows_examined;
+        }
+    }
+}
+
+fn dim_values(q: &Query, dim: &GroupDim, zone: &Zone) -> Vec<String> {
+    match dim {
+        &GroupDim::Db => vec![q.db.clone()],
+        &GroupDim::User => vec![q.user.clone()],
+        &GroupDim::Host => vec![q.host.clone()],
+        &GroupDim::Ip => vec![q.ip.clone()],
+        &GroupDim::Statement => vec![q.statement.clone()],
+        &GroupDim::Table =>
+            if q.tables.len() > 0 { q.tables.clone() } else { vec!["?".to_string()] },
+        &GroupDim::Hour =>
+            if q.timestamp >= 0 {
+                vec![format!("{:02}", zone.hour(q.timestamp))]
+            } else {
+                vec!["?".to_string()]
+            },
+        &GroupDim::Tag(ref key) => vec![q.tags.get(key).cloned().unwrap_or_else(|| "?".to_string())],
+        &GroupDim::Undefined => vec!["?".to_string()]
+    }
+}
+
+/// Every combination of dimension values, so a query touching two tables
+/// is accounted in both table groups.
+fn group_keys(q: &Query, dims: &Vec<GroupDim>, zone: &Zone) -> Vec<Vec<String>> {
+    let mut keys: Vec<Vec<String>> = vec![Vec::new()];
+
+    for dim in dims.iter() {
+        let values = dim_values(q, dim, zone);
+        let mut next_keys: Vec<Vec<String>> = Vec::with_capacity(keys.len() * values.len());
+
+        for key in keys.iter() {
+            for value in values.iter() {
+                let mut next_key = key.clone();
+                next_key.push(value.clone());
+                next_keys.push(next_key);
+            }
+        }
+
+        keys = next_keys;
+    }
+
+    keys
+}
+
+pub fn report(qq: &Vec<Query>, cnf: &Config) -> String {
+    let dims = &cnf.group_by;
+    let mut groups: HashMap<Vec<String>, GroupStat> = HashMap::new();
+
+    for q in qq.iter() {
+        for key in group_keys(q, dims, &cnf.display_timezone) {
+            groups.entry(key).or_insert_with(GroupStat::new).add(q);
+        }
+    }
+
+    let mut rows: Vec<(Vec<String>, GroupStat)> = groups.into_iter().collect();
+
+    rows.sort_by(|lhs, rhs| rhs.1.total_time.partial_cmp(&lhs.1.total_time).unwrap()
+        .then_with(|| lhs.0.cmp(&rhs.0)));
+
+    let headers: Vec<String> = dims.iter().map(|dim| dim.to_string()).collect();
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+
+    for &(ref key, _) in rows.iter() {
+        for (index, value) in key.iter().enumerate() {
+            widths[index] = widths[index].max(value.chars().count());
+        }
+    }
+
+    let mut buf = "GROUP BY REPORT:\n".to_string();
+
+    for (index, header) in headers.iter().enumerate() {
+        buf.push_str(&format!("{:<width$} ", header, width = widths[index]));
+    }
+
+    buf.push_str(&format!("{:>10} {:>14} {:>12} {:>12} {:>14} {:>12}\n",
+                          "COUNT", "TOTAL_TIME", "AVG_TIME", "P95_TIME", "ROWS_EXAMINED", "AVG_EXAMINED"));
+
+    for &mut (ref key, ref mut stat) in rows.iter_mut() {
+        for (index, value) in key.iter().enumerate() {
+            buf.push_str(&format!("{:<width$} ", value, width = widths[index]));
+        }
+
+        let p95 = stats::percentile(&mut stat.times, 95.0);
+
+        buf.push_str(&format!("{:>10} {:>14.6} {:>12.6} {:>12.6} {:>14} {:>12.1}\n",
+                              stat.count,
+                              stat.total_time,
+                              stat.total_time / stat.count as f64,
+                              p95,
+                              stat.rows_examined,
+                              stat.rows_examined as f64 / stat.count as f64));
+    }
+
+    buf
+}
@@ -0,0 +1,105 @@
+use types::{Query, Config, QueryView};
+use processing::Snapshot;
+
+/// JSON string literal of value
+pub fn string(value: &str) -> String {
+    let mut buf = String::with_capacity(value.len() + 2);
+
+    buf.push('"');
+
+    for ch in value.chars() {
+        match ch {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => buf.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => buf.push(ch)
+        }
+    }
+
+    buf.push('"');
+    buf
+}
+
+/// Unknown values, negative numbers and "?", are null
+fn text(value: &str) -> String {
+    if value == "?" { "null".to_string() } else { string(value) }
+}
+
+fn integer(value: i64) -> String {
+    if value >= 0 { value.to_string() } else { "null".to_string() }
+}
+
+fn float(value: f64) -> String {
+    if value >= 0.0 { value.to_string() } else { "null".to_string() }
+}
+
+/// Query entry, timestamps in display timezone and format
+fn query(snapshot: &Snapshot, q: &Query, cnf: &Config) -> String {
+    let mut fields: Vec<(&str, String)> = vec![
+        ("date_time", if q.timestamp >= 0 { string(&cnf.format_timestamp(q.timestamp)) } else { "null".to_string() }),
+        ("timestamp", integer(q.timestamp)),
+        ("db", text(&q.db)),
+        ("user", text(&q.user)),
+        ("host", text(&q.host)),
+        ("query_time", float(q.query_time)),
+        ("lock_time", float(q.lock_time)),
+        ("rows_sent", integer(q.rows_sent)),
+        ("rows_examined", integer(q.rows_examined)),
+        ("rows_affected", integer(q.rows_affected)),
+        ("count", snapshot.count(q).to_string())
+    ];
+
+    if q.tags.len() > 0 {
+        let tags: Vec<String> = q.tags.iter().map(|(key, value)| format!("{}: {}", string(key), string(value))).collect();
+
+        fields.push(("tags", format!("{{{}}}", tags.join(", "))));
+    }
+
+    if let Some(group) = snapshot.groups.get(&q.fingerprint) {
+        fields.push(("group", format!("{{\"count\": {}, \"total_time\": {}, \"max_time\": {}, \"rows_examined\": {}}}",
+                                      group.count, group.query_time, group.max_query_time, group.rows_examined)));
+    }
+
+    match cnf.query_view {
+        QueryView::Raw => fields.push(("query", string(&q.raw_query))),
+        QueryView::Fingerprint => fields.push(("query", string(&q.fingerprint))),
+        QueryView::Sample => {
+            fields.push(("query", string(&q.fingerprint)));
+            fields.push(("sample", string(snapshot.sample(q).unwrap_or(&q.raw_query))));
+        }
+        QueryView::Both => {
+            fields.push(("query", string(&q.fingerprint)));
+            fields.push(("raw_query", string(&q.raw_query)));
+        }
+        _ => fields.push(("query", string(&q.query)))
+    }
+
+    let fields: Vec<String> = fields.into_iter().map(|(name, value)| format!("{}: {}", string(name), value)).collect();
+
+    format!("{{{}}}", fields.join(", "))
+}
+
+/// Listed queries and totals as one JSON object
+pub fn snapshot(snapshot: &Snapshot, cnf: &Config) -> String {
+    let queries: Vec<String> = snapshot.shown().into_iter().map(|q| format!("    {}", query(snapshot, q, cnf))).collect();
+    let mut buf = "{\n".to_string();
+
+    if queries.len() > 0 {
+        buf.push_str(&format!("  \"queries\": [\n{}\n  ],\n", queries.join(",\n")));
+    } else {
+        buf.push_str("  \"queries\": [],\n");
+    }
+
+    if cnf.lint.len() > 0 {
+        buf.push_str(&format!("  \"lint\": {},\n", snapshot.findings.to_json()));
+    }
+
+    buf.push_str(&format!("  \"timezone\": {},\n", string(&cnf.display_timezone.to_string())));
+    buf.push_str(&format!("  \"total\": {},\n  \"filtered\": {},\n  \"duplicates\": {},\n  \"shown\": {}\n}}",
+                          snapshot.total, snapshot.filtered_out, snapshot.duplicates, snapshot.order.len()));
+
+    buf
+}
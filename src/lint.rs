@@ -0,0 +1,356 @@
+use types::{Query, LintRule};
+use sql::{self, Token};
+use regs;
+use json;
+use std::collections::HashMap;
+
+/// Pagination offset reported by large_offset rule
+const LARGE_OFFSET: u64 = 10000;
+
+/// Column names that usually have an index on them
+const INDEXED_NAMES: &'static [&'static str] = &["id", "uuid", "email", "code", "date", "created", "updated"];
+
+const INDEXED_SUFFIXES: &'static [&'static str] = &["_id", "_uuid", "_key", "_code", "_at", "_on", "_date", "_time"];
+
+const SELECT_MODIFIERS: &'static [&'static str] = &[
+    "SELECT", "DISTINCT", "DISTINCTROW", "ALL", "HIGH_PRIORITY", "STRAIGHT_JOIN",
+    "SQL_SMALL_RESULT", "SQL_BIG_RESULT", "SQL_BUFFER_RESULT", "SQL_CACHE", "SQL_NO_CACHE", "SQL_CALC_FOUND_ROWS"
+];
+
+/// Words that look like identifiers but are never column names in conditions
+const NOT_COLUMNS: &'static [&'static str] = &[
+    "AND", "OR", "XOR", "NOT", "NULL", "TRUE", "FALSE", "IS", "IN", "LIKE", "REGEXP", "BETWEEN", "EXISTS",
+    "CASE", "WHEN", "THEN", "ELSE", "END", "INTERVAL", "BINARY", "ANY", "SOME", "ALL", "DISTINCT", "AS"
+];
+
+const COMPARISONS: &'static [&'static str] = &["=", "<", ">", "<=", ">=", "<>", "!="];
+
+/// Ends a WHERE clause at the same parentheses depth
+const CLAUSE_ENDS: &'static [&'static str] = &["GROUP", "ORDER", "LIMIT", "HAVING", "UNION", "FOR", "LOCK", "WINDOW", "INTO"];
+
+fn is_column(token: &Token) -> bool {
+    match token {
+        &Token::Quoted(_) => true,
+        &Token::Word(ref word) =>
+            !word.starts_with('@') && !sql::is_reserved(token) && !NOT_COLUMNS.iter().any(|not_column| token.is_word(not_column)),
+        _ => false
+    }
+}
+
+/// Column reference at index (not a function call), e.g. db.table.column
+fn is_column_ref(tokens: &[Token], index: usize) -> bool {
+    let mut next = index + 1;
+
+    if !tokens.get(index).map(is_column).unwrap_or(false) {
+        return false;
+    }
+
+    while next + 1 < tokens.len() && tokens[next].is_punct(".") && is_column(&tokens[next + 1]) {
+        next += 2;
+    }
+
+    !tokens.get(next).map(|token| token.is_punct("(")).unwrap_or(false)
+}
+
+fn is_indexed_looking(token: &Token) -> bool {
+    let name = match token {
+        &Token::Word(ref value) | &Token::Quoted(ref value) => value.to_lowercase(),
+        _ => return false
+    };
+
+    INDEXED_NAMES.contains(&&*name) || INDEXED_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+fn is_comparison(token: &Token) -> bool {
+    COMPARISONS.iter().any(|op| token.is_punct(op)) ||
+        ["LIKE", "IN", "BETWEEN", "REGEXP", "IS"].iter().any(|word| token.is_word(word))
+}
+
+fn number(token: Option<&Token>) -> Option<u64> {
+    match token {
+        Some(&Token::Number(ref value)) => value.parse::<u64>().ok(),
+        _ => None
+    }
+}
+
+fn select_star(tokens: &[Token]) -> bool {
+    tokens.iter().enumerate().any(|(index, token)| {
+        token.is_punct("*") && index > 0 && {
+            let prev = &tokens[index - 1];
+
+            prev.is_punct(",") || prev.is_punct(".") || SELECT_MODIFIERS.iter().any(|word| prev.is_word(word))
+        } && !(index >= 3 && tokens[index - 2].is_punct("(") && tokens[index - 3].is_word("EXISTS"))
+    })
+}
+
+fn leading_wildcard(tokens: &[Token]) -> bool {
+    let is_wildcard = |token: Option<&Token>| match token {
+        Some(&Token::Str(ref value)) => value.starts_with('%') || value.starts_with('_'),
+        _ => false
+    };
+
+    tokens.iter().enumerate().any(|(index, token)|
+        token.is_word("LIKE") && (
+            is_wildcard(tokens.get(index + 1)) ||
+                (tokens.get(index + 1).map(|next| next.is_word("CONCAT")).unwrap_or(false) && is_wildcard(tokens.get(index + 3)))
+        ))
+}
+
+fn order_by_rand(tokens: &[Token]) -> bool {
+    for index in 0..tokens.len() {
+        if !(tokens[index].is_word("ORDER") && tokens.get(index + 1).map(|next| next.is_word("BY")).unwrap_or(false)) {
+            continue;
+        }
+
+        let mut depth = 0;
+
+        for next in index + 2..tokens.len() {
+            let token = &tokens[next];
+
+            if token.is_punct("(") {
+                depth += 1;
+            } else if token.is_punct(")") {
+                if depth == 0 {
+                    break;
+                }
+
+                depth -= 1;
+            } else if depth == 0 && (token.is_word("LIMIT") || token.is_punct(";")) {
+                break;
+            } else if (token.is_word("RAND") || token.is_word("RANDOM")) &&
+                tokens.get(next + 1).map(|after| after.is_punct("(")).unwrap_or(false) {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+fn no_where(tokens: &[Token]) -> bool {
+    let statement = sql::statement_type(tokens);
+
+    if statement != "UPDATE" && statement != "DELETE" {
+        return false;
+    }
+
+    let mut depth = 0;
+
+    for token in tokens.iter() {
+        if token.is_punct("(") {
+            depth += 1;
+        } else if token.is_punct(")") {
+            depth -= 1;
+        } else if depth == 0 && token.is_word("WHERE") {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Whether function call at index wraps an indexed-looking column and is compared
+fn wrapped_column(tokens: &[Token], index: usize) -> bool {
+    if !is_column(&tokens[index]) || !tokens.get(index + 1).map(|next| next.is_punct("(")).unwrap_or(false) {
+        return false;
+    }
+
+    let end = sql::skip_parens(tokens, index + 1);
+
+    let wraps = (index + 2..end.saturating_sub(1)).any(|arg|
+        is_column_ref(tokens, arg) && is_indexed_looking(&tokens[arg]) &&
+            !tokens.get(arg + 1).map(|next| next.is_punct(".")).unwrap_or(false));
+
+    wraps && (tokens.get(end).map(is_comparison).unwrap_or(false) ||
+        (index > 0 && COMPARISONS.iter().any(|op| tokens[index - 1].is_punct(op))))
+}
+
+fn function_on_column(tokens: &[Token]) -> bool {
+    for index in 0..tokens.len() {
+        if !tokens[index].is_word("WHERE") {
+            continue;
+        }
+
+        let mut depth = 0;
+
+        for next in index + 1..tokens.len() {
+            let token = &tokens[next];
+
+            if token.is_punct("(") {
+                depth += 1;
+            } else if token.is_punct(")") {
+                if depth == 0 {
+                    break;
+                }
+
+                depth -= 1;
+            } else if depth == 0 && CLAUSE_ENDS.iter().any(|word| token.is_word(word)) {
+                break;
+            } else if wrapped_column(tokens, next) {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+fn large_offset(tokens: &[Token]) -> bool {
+    tokens.iter().enumerate().any(|(index, token)|
+        (token.is_word("OFFSET") && number(tokens.get(index + 1)).map(|offset| offset >= LARGE_OFFSET).unwrap_or(false)) ||
+            (token.is_word("LIMIT") && tokens.get(index + 2).map(|next| next.is_punct(",")).unwrap_or(false) &&
+                number(tokens.get(index + 1)).map(|offset| offset >= LARGE_OFFSET).unwrap_or(false)))
+}
+
+fn not_in_subquery(tokens: &[Token]) -> bool {
+    tokens.windows(4).any(|window|
+        window[0].is_word("NOT") && window[1].is_word("IN") && window[2].is_punct("(") && window[3].is_word("SELECT"))
+}
+
+/// Number of comma separated table references after FROM at index
+fn from_list_len(tokens: &[Token], index: usize) -> usize {
+    let mut index = index + 1;
+    let mut count = 0;
+
+    loop {
+        if tokens.get(index).map(|token| token.is_punct("(")).unwrap_or(false) {
+            index = sql::skip_parens(tokens, index);
+        } else if tokens.get(index).map(is_column).unwrap_or(false) {
+            index += 1;
+
+            while index + 1 < tokens.len() && tokens[index].is_punct(".") && is_column(&tokens[index + 1]) {
+                index += 2;
+            }
+        } else {
+            return count;
+        }
+
+        count += 1;
+
+        if tokens.get(index).map(|token| token.is_word("AS")).unwrap_or(false) {
+            index += 1;
+        }
+
+        if tokens.get(index).map(is_column).unwrap_or(false) {
+            index += 1;
+        }
+
+        if !tokens.get(index).map(|token| token.is_punct(",")).unwrap_or(false) {
+            return count;
+        }
+
+        index += 1;
+    }
+}
+
+/// Tables listed with commas and no column to column condition between them
+fn cross_join(tokens: &[Token]) -> bool {
+    let comma_join = tokens.iter().enumerate()
+        .any(|(index, token)| token.is_word("FROM") && from_list_len(tokens, index) > 1);
+
+    let join_condition = tokens.iter().enumerate().any(|(index, token)|
+        index > 0 && COMPARISONS.iter().any(|op| token.is_punct(op)) &&
+            is_column(&tokens[index - 1]) && is_column_ref(tokens, index + 1));
+
+    comma_join && !join_condition
+}
+
+/// Rules broken by query
+pub fn check(query: &str, rules: &Vec<LintRule>) -> Vec<LintRule> {
+    let tokens = sql::tokenize(query);
+
+    rules.iter().filter(|rule| match *rule {
+        &LintRule::SelectStar => select_star(&tokens),
+        &LintRule::LeadingWildcard => leading_wildcard(&tokens),
+        &LintRule::OrderByRand => order_by_rand(&tokens),
+        &LintRule::NoWhere => no_where(&tokens),
+        &LintRule::FunctionOnColumn => function_on_column(&tokens),
+        &LintRule::LargeOffset => large_offset(&tokens),
+        &LintRule::NotInSubquery => not_in_subquery(&tokens),
+        &LintRule::CrossJoin => cross_join(&tokens)
+    }).cloned().collect()
+}
+
+#[derive(Clone)]
+struct Cost {
+    count: usize,
+    total_time: f64
+}
+
+/// Broken rules per fingerprint with count and time of queries that broke them
+#[derive(Clone)]
+pub struct Findings {
+    costs: HashMap<(LintRule, String), Cost>
+}
+
+impl Findings {
+    pub fn new() -> Self {
+        Self {
+            costs: HashMap::new()
+        }
+    }
+
+    /// Literals are hidden in fingerprint, so the query text itself is checked
+    pub fn add(&mut self, q: &Query, rules: &Vec<LintRule>) {
+        if rules.len() == 0 {
+            return;
+        }
+
+        for rule in check(&regs::remove_comments(&q.raw_query), rules) {
+            let cost = self.costs.entry((rule, q.fingerprint.clone()))
+                .or_insert(Cost { count: 0, total_time: 0.0 });
+
+            cost.count += 1;
+
+            if q.query_time > 0.0 {
+                cost.total_time += q.query_time;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: Findings) {
+        for (key, other_cost) in other.costs.into_iter() {
+            let cost = self.costs.entry(key).or_insert(Cost { count: 0, total_time: 0.0 });
+
+            cost.count += other_cost.count;
+            cost.total_time += other_cost.total_time;
+        }
+    }
+
+    /// Findings sorted by total time of queries that broke the rule
+    fn rows(&self) -> Vec<(&(LintRule, String), &Cost)> {
+        let mut rows: Vec<(&(LintRule, String), &Cost)> = self.costs.iter().collect();
+
+        rows.sort_by(|lhs, rhs| rhs.1.total_time.partial_cmp(&lhs.1.total_time).unwrap()
+            .then_with(|| lhs.0.cmp(rhs.0)));
+
+        rows
+    }
+
+    /// JSON array of findings in report order
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = self.rows().into_iter().map(|(&(ref rule, ref fingerprint), cost)|
+            format!("    {{\"rule\": {}, \"count\": {}, \"total_time\": {}, \"fingerprint\": {}}}",
+                    json::string(&rule.to_string()), cost.count, cost.total_time, json::string(fingerprint)))
+            .collect();
+
+        if rows.len() == 0 {
+            return "[]".to_string();
+        }
+
+        format!("[\n{}\n  ]", rows.join(",\n"))
+    }
+
+    /// Findings as text table in report order
+    pub fn to_string(&self) -> String {
+        let rows = self.rows();
+        let mut buf = format!("LINT REPORT:\n{:<20} {:>10} {:>14} {}\n", "RULE", "COUNT", "TOTAL_TIME", "FINGERPRINT");
+
+        for &(&(ref rule, ref fingerprint), cost) in rows.iter() {
+            buf.push_str(&format!("{:<20} {:>10} {:>14.6} {}\n",
+                                  rule.to_string(), cost.count, cost.total_time, fingerprint));
+        }
+
+        buf
+    }
+}
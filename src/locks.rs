@@ -0,0 +1,162 @@
+use types::{Query, Config};
+use std::collections::{HashMap, BTreeMap};
+
+/// Fingerprints shown ranked by lock time
+const FINGERPRINTS_SHOWN: usize = 20;
+
+/// Second is a spike when its lock time is this many times the average
+const SPIKE_FACTOR: f64 = 3.0;
+
+/// Spike windows shown
+const SPIKES_SHOWN: usize = 5;
+
+/// Writes shown per spike window
+const WRITES_SHOWN: usize = 10;
+
+const WRITES: &'static [&'static str] = &["INSERT", "UPDATE", "DELETE", "REPLACE"];
+
+struct LockStat {
+    count: usize,
+    lock_time: f64,
+    query_time: f64
+}
+
+impl LockStat {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            lock_time: 0.0,
+            query_time: 0.0
+        }
+    }
+
+    fn add(&mut self, q: &Query) {
+        self.count += 1;
+
+        if q.lock_time > 0.0 {
+            self.lock_time += q.lock_time;
+        }
+
+        if q.query_time > 0.0 {
+            self.query_time += q.query_time;
+        }
+    }
+
+    fn ratio(&self) -> f64 {
+        if self.query_time > 0.0 { self.lock_time / self.query_time } else { 0.0 }
+    }
+}
+
+fn fingerprints(qq: &Vec<Query>) -> String {
+    let mut stats: HashMap<&String, LockStat> = HashMap::new();
+
+    for q in qq.iter().filter(|q| q.lock_time > 0.0) {
+        stats.entry(&q.fingerprint).or_insert_with(LockStat::new).add(q);
+    }
+
+    let mut rows: Vec<(&String, LockStat)> = stats.into_iter().collect();
+
+    rows.sort_by(|lhs, rhs| rhs.1.lock_time.partial_cmp(&lhs.1.lock_time).unwrap()
+        .then_with(|| lhs.0.cmp(rhs.0)));
+
+    let mut buf = format!("{:>10} {:>14} {:>12} {:>10} {}\n", "COUNT", "LOCK_TIME", "AVG_LOCK", "LOCK/QT", "FINGERPRINT");
+
+    for &(fingerprint, ref stat) in rows.iter().take(FINGERPRINTS_SHOWN) {
+        buf.push_str(&format!("{:>10} {:>14.6} {:>12.6} {:>10.3} {}\n",
+                              stat.count, stat.lock_time, stat.lock_time / stat.count as f64, stat.ratio(), fingerprint));
+    }
+
+    buf
+}
+
+/// Consecutive seconds with lock time over SPIKE_FACTOR times the average
+/// of the whole time span: (first second, last second, lock time)
+fn spikes(qq: &Vec<Query>) -> Vec<(i64, i64, f64)> {
+    let mut seconds: BTreeMap<i64, f64> = BTreeMap::new();
+
+    for q in qq.iter().filter(|q| q.timestamp >= 0 && q.lock_time > 0.0) {
+        *seconds.entry(q.start().floor() as i64).or_insert(0.0) += q.lock_time;
+    }
+
+    let (first, last) = match (seconds.keys().next(), seconds.keys().next_back()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return Vec::new()
+    };
+
+    let average = seconds.values().sum::<f64>() / (last - first + 1) as f64;
+    let mut windows: Vec<(i64, i64, f64)> = Vec::new();
+
+    for (&second, &lock_time) in seconds.iter().filter(|&(_, &lock_time)| lock_time >= average * SPIKE_FACTOR) {
+        if let Some(window) = windows.last_mut() {
+            if window.1 + 1 == second {
+                window.1 = second;
+                window.2 += lock_time;
+                continue;
+            }
+        }
+
+        windows.push((second, second, lock_time));
+    }
+
+    windows
+}
+
+/// Writes running during window, by statement and table: count and lock time
+fn writes(qq: &Vec<Query>, from: i64, to: i64) -> Vec<(String, usize, f64)> {
+    let mut writes: HashMap<String, (usize, f64)> = HashMap::new();
+
+    for q in qq.iter().filter(|q| q.timestamp >= 0 && WRITES.contains(&&*q.statement)) {
+        if q.start() >= (to + 1) as f64 || (q.timestamp as f64) < from as f64 {
+            continue;
+        }
+
+        let tables = if q.tables.len() > 0 { q.tables.clone() } else { vec!["?".to_string()] };
+
+        for table in tables {
+            let write = writes.entry(format!("{} {}", q.statement, table)).or_insert((0, 0.0));
+
+            write.0 += 1;
+            write.1 += q.lock_time.max(0.0);
+        }
+    }
+
+    let mut rows: Vec<(String, usize, f64)> = writes.into_iter().map(|(key, (count, lock_time))| (key, count, lock_time)).collect();
+
+    rows.sort_by(|lhs, rhs| rhs.2.partial_cmp(&lhs.2).unwrap()
+        .then_with(|| rhs.1.cmp(&lhs.1))
+        .then_with(|| lhs.0.cmp(&rhs.0)));
+
+    rows
+}
+
+pub fn report(qq: &Vec<Query>, cnf: &Config) -> String {
+    let mut buf = format!("LOCKS REPORT:\n{}", fingerprints(qq));
+    let mut windows = spikes(qq);
+
+    windows.sort_by(|lhs, rhs| rhs.2.partial_cmp(&lhs.2).unwrap().then_with(|| lhs.0.cmp(&rhs.0)));
+
+    buf.push_str(&format!("\nLock time spikes ({}x average or more):\n", SPIKE_FACTOR));
+
+    if windows.len() == 0 {
+        buf.push_str("\tNone\n");
+        return buf;
+    }
+
+    buf.push_str(&format!("\t{:<24} {:>10} {:>14}\n", "FROM", "DURATION", "LOCK_TIME"));
+
+    for &(from, to, lock_time) in windows.iter().take(SPIKES_SHOWN) {
+        buf.push_str(&format!("\t{:<24} {:>10} {:>14.6}\n", cnf.format_timestamp(from), to - from + 1, lock_time));
+
+        let writes = writes(qq, from, to);
+
+        for &(ref write, count, write_lock_time) in writes.iter().take(WRITES_SHOWN) {
+            buf.push_str(&format!("\t\t{:<40} {:>6} {:>14.6}\n", write, count, write_lock_time));
+        }
+
+        if writes.len() > WRITES_SHOWN {
+            buf.push_str(&format!("\t\t... {} more\n", writes.len() - WRITES_SHOWN));
+        }
+    }
+
+    buf
+}
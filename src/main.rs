@@ -1,139 +1,222 @@
 #![feature(plugin)]
 #![plugin(rocket_codegen)]
+// &String and &Vec parameters, len() comparisons, &Enum patterns and long
+// parameter lists are the code style here
+#![cfg_attr(feature = "cargo-clippy", allow(ptr_arg, len_zero, match_ref_pats, const_static_lifetime,
+                                            too_many_arguments, cyclomatic_complexity))]
 
 #[macro_use] extern crate lazy_static;
 extern crate regex;
 extern crate chrono;
+extern crate chrono_tz;
 extern crate clap;
+extern crate num_cpus;
 extern crate rocket;
 
 mod regs;
 mod types;
 mod processing;
+mod sql;
+mod reports;
+mod stats;
+mod groups;
+mod concurrency;
+mod locks;
+mod sessions;
+mod bursts;
+mod baseline;
+mod gate;
+mod expr;
+mod datetime;
+mod state;
+mod parser;
+mod aggregate;
+mod lint;
+mod json;
 mod web;
 
+use std::env;
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{BufReader, Read};
-use std::sync::Mutex;
-use chrono::TimeZone;
-use chrono::prelude::Utc;
-use clap::{App, Arg};
-use types::{Query, Config, QueriesSortType};
-use regex::Regex;
-use std::thread::sleep;
-use std::time::Duration;
-
-lazy_static! {
-    static ref queries: Mutex<Vec<Query>> = Mutex::new(Vec::new());
-}
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::process;
+use clap::{App, Arg, ArgMatches};
+use types::{Query, Config, SortKey, QueryView, OutputFormat, ReportType, GroupDim, DedupKeep, LintRule};
+use regex::{Regex, RegexBuilder};
+use baseline::{Baseline, Threshold};
 
-lazy_static! {
-    static ref config: Mutex<Config> = Mutex::new(Config::new());
-}
+/// Exit code when a CI gate check is breached
+const EXIT_BREACH: i32 = 1;
+
+/// Exit code on invalid arguments
+const EXIT_USAGE: i32 = 2;
+
+/// Exit code when the log can't be read
+const EXIT_PARSE: i32 = 3;
+
+/// Exit code when the baseline file can't be written
+const EXIT_WRITE: i32 = 4;
 
 fn main() {
-    match configure() {
+    let cnf = match configure() {
+        Ok(cnf) => cnf,
         Err(err) => {
             println!("Can't continue due errors:\n{}", err);
-            return;
+            process::exit(EXIT_USAGE);
         }
-        _ => {}
-    }
+    };
+
+    if cnf.aggregate {
+        let cnf = Arc::new(cnf);
+        let fold_cnf = cnf.clone();
+        let mut total = aggregate::Aggregate::new();
+        let chunks = read_log(&cnf, false, &aggregate::Aggregate::new(),
+                              move |agg: &mut aggregate::Aggregate, q| agg.add(q, &fold_cnf));
 
-    read_queries(false);
+        for chunk in chunks.unwrap_or_else(|| process::exit(EXIT_PARSE)) {
+            total.merge(chunk);
+        }
 
-    {
-        let mut qq = queries.lock().unwrap();
-        processing::process(&mut qq, false);
+        println!("{}", total.to_string(&cnf));
+        return;
     }
 
-    {
-        if config.lock().unwrap().web_port > 0 {
-            web::invoke_web();
+    let qq = read_queries(&cnf, false).unwrap_or_else(|| process::exit(EXIT_PARSE));
+    let snapshot = processing::process(qq, &cnf);
+
+    processing::print(&snapshot, &cnf);
+
+    if let Some(ref profile) = snapshot.profile {
+        if cnf.save_baseline.len() > 0 {
+            if let Err(err) = profile.save(&cnf.save_baseline) {
+                eprintln!("Can't save baseline file {}: {}", cnf.save_baseline, err);
+                process::exit(EXIT_WRITE);
+            }
         }
     }
-}
 
-fn read_queries(background: bool) {
-    let log_file = {
-        let cnf = config.lock().unwrap();
+    if snapshot.verdict.as_ref().map(|verdict| verdict.breached > 0).unwrap_or(false) {
+        process::exit(EXIT_BREACH);
+    }
 
-        cnf.log_file.clone()
-    };
+    if cnf.web_port > 0 {
+        web::invoke_web(Arc::new(cnf), snapshot);
+    }
+}
 
-    let wdelay = config.lock().unwrap().wpd;
+fn read_queries(cnf: &Config, background: bool) -> Option<Vec<Query>> {
+    read_log(cnf, background, &Vec::new(), |qq: &mut Vec<Query>, q| qq.push(q))
+        .map(|chunks| chunks.into_iter().flat_map(|qq| qq.into_iter()).collect())
+}
+
+/// Parses the log (or its part appended since the previous run), folding
+/// entries into per-thread copies of init. Results are in log order,
+/// None when the log can't be read.
+fn read_log<A, F>(cnf: &Config, background: bool, init: &A, fold: F) -> Option<Vec<A>>
+    where A: Clone + Send + 'static, F: Fn(&mut A, Query) + Clone + Send + 'static {
+    let (log_file, state_file) = (&cnf.log_file, &cnf.state_file);
 
-    let file = match File::open(&log_file) {
+    let mut file = match File::open(&log_file) {
         Ok(file) => file,
         Err(err) => {
             eprintln!("Can't open file {}: {}", log_file, err);
-            return;
+            return None;
         }
     };
 
-    let mut buf: [u8; 1] = [0];
-    let mut reader = BufReader::new(&file);
-    let mut line = String::new();
-    let mut new_query = Query::new();
-    let mut server_info = String::new();
-    let mut server_info_consumed = false;
-
-    while let Ok(_) = reader.read_exact(&mut buf) {
-        if buf[0] == 0x0A {
-            if !server_info_consumed {
-                if !regs::is_info(&line) {
-                    server_info += &line;
-                    server_info.push_str("\n");
-                    line = String::new();
-                    continue;
-                } else {
-                    server_info_consumed = true;
-                }
-            }
+    let mut offset: u64 = 0;
+    let mut db = "?".to_string();
+    let mut timestamp: i64 = -1;
 
-            if regs::is_info(&line) {
-                handle_info(&line, &mut new_query);
-            } else {
-                handle_raw(&line, &mut new_query);
+    let run_state = if !background && state_file.len() > 0 {
+        let new_state = match state::State::new(&mut file) {
+            Ok(new_state) => new_state,
+            Err(err) => {
+                eprintln!("Can't read file {}: {}", log_file, err);
+                return None;
             }
+        };
 
-            if new_query.valid() {
-                let mut qq = queries.lock().unwrap();
-                let query_index = qq.len();
-                new_query.finish();
+        match state::State::load(state_file) {
+            Ok(prev_state) => {
+                offset = prev_state.resume_offset(&mut file).unwrap_or(0);
 
-                if regs::is_query_end(&new_query.query) {
-                    qq.insert(query_index, new_query);
+                if offset > 0 {
+                    db = prev_state.db;
+                    timestamp = prev_state.timestamp;
                 }
+            }
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => eprintln!("Can't load state file {}, parsing from the beginning: {}", state_file, err)
+        }
 
-                if background {
-                    sleep(wdelay);
-                }
+        Some(new_state)
+    } else {
+        None
+    };
 
-                new_query = Query::new();
-            }
+    let parsed = match parser::fold_file(log_file, offset, cnf.threads, cnf.abs, &cnf.timezone, db, timestamp, init, fold) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("Can't read file {}: {}", log_file, err);
+            return None;
+        }
+    };
 
-            line = String::new();
-        } else {
-            line += &String::from_utf8_lossy(&buf);
+    if let Some(mut run_state) = run_state {
+        run_state.offset = parsed.consumed;
+        run_state.db = parsed.db;
+        run_state.timestamp = parsed.timestamp;
+
+        if let Err(err) = run_state.save(state_file) {
+            eprintln!("Can't save state file {}: {}", state_file, err);
         }
     }
 
-    if server_info.len() > 0 && !background {
-        let si_split: Vec<&str> = server_info.split("\n").collect();
+    if parsed.server_info.len() > 0 && !background && !cnf.json() {
+        let si_split: Vec<&str> = parsed.server_info.split('\n').collect();
         let info_string = si_split[0].to_string();
         println!("SERVER INFO: {}\n", info_string.replace(". started with:", ""));
     }
+
+    Some(parsed.results)
 }
 
-fn configure() -> Result<(), String> {
-    let mut cnf = config.lock().unwrap();
+/// Joins `--lint -RULE,...` into `--lint=-RULE,...`. Clap takes -RULE for short
+/// flags, and with hyphen values allowed --lint would swallow the flags after it
+fn join_lint_negations<I>(args: I) -> Vec<OsString> where I: Iterator<Item = OsString> {
+    let mut joined: Vec<OsString> = Vec::new();
+
+    for arg in args {
+        let rules = arg.to_str().filter(|value| value.starts_with('-') &&
+            value.split(',').all(|name| LintRule::parse(name.trim_start_matches('-')).is_some())).map(|value| value.to_string());
+
+        match rules {
+            Some(ref rules) if joined.last().and_then(|last| last.to_str()) == Some("--lint") => {
+                joined.pop();
+                joined.push(OsString::from(format!("--lint={}", rules)));
+            }
+            _ => joined.push(arg)
+        }
+    }
+
+    joined
+}
+
+fn configure() -> Result<Config, String> {
+    let mut cnf = Config::new();
 
     let matches = App::new("MySQL slow log parser")
         .version("1.1.7")
         .author("Developed by Alexander Kozharsky <a.kozharsky@southbridge.io>
 Copyright (c) Southbridge, LLC https://southbridge.io")
         .about("Parses MySQL slow log very fast")
+        .after_help("EXIT CODES:
+    1    CI gate check failed
+    2    Invalid arguments
+    3    Log can't be read
+    4    Baseline file can't be written")
         .arg(Arg::with_name("file")
             .short("f")
             .long("file")
@@ -143,12 +226,43 @@ Copyright (c) Southbridge, LLC https://southbridge.io")
             .long("ts_min")
             .value_name("TIMESTAMP_MIN")
             .help("Timestamp range minimum value
-  format: Unix timestamp or DD/MM/YYYY"))
+  format: Unix timestamp, DD/MM/YYYY[ HH:MM[:SS]],
+  YYYY-MM-DD[ HH:MM[:SS]] or RFC3339"))
         .arg(Arg::with_name("ts_max")
             .long("ts_max")
             .value_name("TIMESTAMP_MAX")
             .help("Timestamp range maximum value
-  format: Unix timestamp or DD/MM/YYYY"))
+  format: Unix timestamp, DD/MM/YYYY[ HH:MM[:SS]],
+  YYYY-MM-DD[ HH:MM[:SS]] or RFC3339"))
+        .arg(Arg::with_name("since")
+            .long("since")
+            .value_name("DURATION")
+            .help("Show queries since DURATION ago, e.g. 2h, 30m, 1d12h"))
+        .arg(Arg::with_name("until")
+            .long("until")
+            .value_name("DURATION")
+            .help("Show queries until DURATION ago"))
+        .arg(Arg::with_name("last")
+            .long("last")
+            .value_name("DURATION")
+            .help("Show queries for last DURATION of the log,
+  measured from the newest entry"))
+        .arg(Arg::with_name("timezone")
+            .long("timezone")
+            .value_name("TIMEZONE")
+            .help("Timezone to parse dates and show timestamps, also of old
+  style # Time: YYMMDD HH:MM:SS stamps of the log,
+  format: IANA name like Europe/Moscow, UTC or offset
+  like +03:00 (UTC by default)"))
+        .arg(Arg::with_name("display_timezone")
+            .long("display_timezone")
+            .value_name("TIMEZONE")
+            .help("Timezone to show timestamps, --timezone by default"))
+        .arg(Arg::with_name("dt_format")
+            .long("dt_format")
+            .value_name("FORMAT")
+            .help("Output date and time format, strftime syntax
+  (%d/%m/%Y:%H:%M:%S by default)"))
         .arg(Arg::with_name("database")
             .long("database")
             .value_name("DATABASE")
@@ -196,35 +310,179 @@ Copyright (c) Southbridge, LLC https://southbridge.io")
         .arg(Arg::with_name("sort_type")
             .short("s")
             .long("sort_type")
-            .value_name("SORT_TYPE")
-            .help("Sort by column parameter, where SORT_TYPE:
-  ts   - Timestamp
-  qt   - Query time
-  lt   - Lock time
-  rs   - Rows sent
-  re   - Rows examined
-  ra   - Rows affected
-  tsi  - Timestamp inverse
-  qti  - Query time inverse
-  lti  - Lock time inverse
-  rsi  - Rows sent inverse
-  rei  - Rows examined inverse
-  rai  - Rows affected inverse
-  cnt  - Count
-  cnti - Count inverse"))
+            .alias("sort")
+            .value_name("KEYS")
+            .help("Sort by comma separated KEYS, each is FIELD[:asc|:desc],
+e.g. \"qt:desc,re:desc,ts:asc\". FIELD is one of:
+  ts qt lt rs re ra     - Timestamp, query and lock time, rows sent,
+                          examined and affected
+  db user host ip stmt fp
+  tag:<KEY>             - Comment tag value
+  cnt                   - Count of the same queries
+  sum_qt avg_qt max_qt  - Total, average and maximum query time
+                          of the same queries
+  sum_re                - Total rows examined by the same queries
+  re_rs                 - Rows examined per row sent by queries
+                          of the same fingerprint
+  bytes_sent, tmp_disk_tables, innodb_io_r_wait, ...
+                        - Extended fields of Percona Server and
+                          MySQL 8 log_slow_extra
+tsi, qti, lti, rsi, rei, rai and cnti are kept for descending order.
+In aggregate mode only ts (first seen), fp and the same queries
+totals apply, sorted by sum_qt:desc by default"))
+        .arg(Arg::with_name("query_view")
+            .short("v")
+            .long("query_view")
+            .value_name("VIEW")
+            .help("Query text to show, where VIEW:
+  query  - Query with comments removed (default)
+  raw    - Original query text as logged
+  fp     - Fingerprint (strings and numbers abstracted)
+  sample - Fingerprint with one real example
+  both   - Fingerprint with original query text"))
+        .arg(Arg::with_name("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Output format, where FORMAT:
+  text - Entries and reports as text (default)
+  json - Listed queries, lint findings and totals as a JSON
+         object, timestamps in --display_timezone and --dt_format"))
         .arg(Arg::with_name("query_regex")
             .short("r")
             .long("query_regex")
             .value_name("REGEX_STRING")
-            .help("Query regex filter"))
+            .multiple(true)
+            .number_of_values(1)
+            .help("Query regex filter
+  Can be repeated, any regex must match"))
+        .arg(Arg::with_name("query_iregex")
+            .long("query_iregex")
+            .value_name("REGEX_STRING")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Case-insensitive query regex filter"))
+        .arg(Arg::with_name("exclude_regex")
+            .short("x")
+            .long("exclude_regex")
+            .value_name("REGEX_STRING")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Exclude queries matching regex
+  Can be repeated, no regex must match"))
+        .arg(Arg::with_name("exclude_iregex")
+            .long("exclude_iregex")
+            .value_name("REGEX_STRING")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Case-insensitive exclude regex"))
+        .arg(Arg::with_name("ignore_noise")
+            .long("ignore_noise")
+            .help("Exclude common noise: SELECT @@, SHOW, SET, COMMIT,
+ROLLBACK, FLUSH, backup queries (SQL_NO_CACHE) etc."))
+        .arg(Arg::with_name("tag")
+            .short("t")
+            .long("tag")
+            .value_name("KEY[=VALUE]")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Comment tag filter, e.g. controller=Users#show
+  Tags are parsed from /* key:value */ query comments
+  Can be repeated, all tags must match"))
+        .arg(Arg::with_name("table")
+            .long("table")
+            .value_name("TABLE")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Table name filter, db.table or table
+  Can be repeated, any table must match"))
+        .arg(Arg::with_name("report")
+            .long("report")
+            .value_name("REPORT")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Print report after queries list, where REPORT:
+  tables      - Count and query time per table and statement type
+  summary     - Time span, QPS, totals and time/rows histograms
+  concurrency - Queries running at once over time, peak windows
+                and fingerprints running during lock pile-ups
+  locks       - Fingerprints by lock time, lock time spikes
+                and writes by table running during them
+  sessions    - Statements per connection (User@Host Id or Thread_id)
+                and longest transactions with their statements
+  nplus1      - Bursts of the same fingerprint from one user, host
+                and connection, see --burst_min and --burst_window"))
+        .arg(Arg::with_name("burst_min")
+            .long("burst_min")
+            .value_name("COUNT")
+            .help("Repetitions making an N+1 burst (10 by default)"))
+        .arg(Arg::with_name("burst_window")
+            .long("burst_window")
+            .value_name("SECONDS")
+            .help("Longest pause between queries of an N+1 burst, whole seconds
+  as log timestamps have 1 second precision (1 by default)"))
+        .arg(Arg::with_name("save_baseline")
+            .long("save_baseline")
+            .value_name("FILE")
+            .help("Save per-fingerprint rate and query time distribution of
+  shown period to FILE, to compare later logs with it by --baseline"))
+        .arg(Arg::with_name("baseline")
+            .long("baseline")
+            .value_name("FILE")
+            .help("Print fingerprints whose rate (queries per minute) or p95
+  query time deviate from baseline saved by --save_baseline,
+  vanished ones included, and fingerprints missing from it,
+  see --anomaly"))
+        .arg(Arg::with_name("anomaly")
+            .long("anomaly")
+            .value_name("THRESHOLD")
+            .help("Deviation from baseline to report: z-score, or percentage
+  of baseline value when ending with % (3 by default)"))
+        .arg(Arg::with_name("lint")
+            .long("lint")
+            .value_name("RULES")
+            .takes_value(true)
+            .min_values(0)
+            .help("Print query anti-patterns per fingerprint, sorted by total time.
+  RULES is comma separated list, all rules by default, -RULE disables rule:
+  select_star        - SELECT *
+  leading_wildcard   - LIKE '%...'
+  order_by_rand      - ORDER BY RAND()
+  no_where           - UPDATE or DELETE without WHERE
+  function_on_column - Function on indexed-looking column in WHERE
+  large_offset       - OFFSET of 10000 rows and more
+  not_in_subquery    - NOT IN (SELECT ...)
+  cross_join         - Comma separated tables without join condition"))
+        .arg(Arg::with_name("group_by")
+            .short("g")
+            .long("group_by")
+            .value_name("DIMENSIONS")
+            .help("Print summary grouped by comma separated DIMENSIONS:
+  db        - Database
+  user      - User
+  host      - Client host
+  ip        - Client IP address
+  stmt      - Statement type
+  table     - Table
+  hour      - Hour of day
+  tag:<KEY> - Comment tag value"))
+        .arg(Arg::with_name("where")
+            .long("where")
+            .value_name("EXPRESSION")
+            .help("Filter expression, e.g. \"re > 1000 * rs OR lt > qt / 2\"
+  fields:    ts qt lt rs re ra db user host ip query raw fp
+             stmt table tag:<KEY> and extended fields
+  operators: + - * / % = != < <= > >= AND OR NOT
+             =~ !~ (regex match), IN (...), NOT IN (...)"))
         .arg(Arg::with_name("cnt_min")
             .long("cnt_min")
             .value_name("COUNT_MIN")
-            .help("Query count minimum value"))
+            .help("Query count minimum value, counting queries of the same
+  text, or of the same fingerprint with --aggregate"))
         .arg(Arg::with_name("cnt_max")
             .long("cnt_max")
             .value_name("COUNT_MAX")
-            .help("Query count maximum value"))
+            .help("Query count maximum value, counting queries of the same
+  text, or of the same fingerprint with --aggregate"))
         .arg(Arg::with_name("limit")
             .short("l")
             .long("limit")
@@ -249,58 +507,129 @@ Port 0 (zero) to disable feature (disabled by default)"))
         .arg(Arg::with_name("dedup")
             .short("d")
             .long("dedup")
-            .help("Remove query duplicates. Shows only last query"))
+            .value_name("KEEP")
+            .takes_value(true)
+            .min_values(0)
+            .help("Show one query per fingerprint, with stats of all collapsed queries.
+  KEEP is the query shown: slowest, examined (most rows examined),
+  first or last (default)"))
+        .arg(Arg::with_name("aggregate")
+            .short("A")
+            .long("aggregate")
+            .help("Show only per-fingerprint totals, percentiles and a few sample queries.
+  Queries are not kept in memory, so it works for logs of any size"))
+        .arg(Arg::with_name("state_file")
+            .long("state_file")
+            .value_name("FILE")
+            .help("Parse only entries appended since the previous run
+  and save position to FILE. Log rotation is detected"))
+        .arg(Arg::with_name("threads")
+            .long("threads")
+            .value_name("N")
+            .help("Parse log in N threads (number of CPUs by default)"))
+        .arg(Arg::with_name("fail_if")
+            .long("fail_if")
+            .value_name("EXPRESSION")
+            .multiple(true)
+            .number_of_values(1)
+            .help("CI gate: fail if any query passing filters matches
+  EXPRESSION, e.g. \"qt > 1.0\", see --where for syntax"))
+        .arg(Arg::with_name("fail_if_new_fingerprint")
+            .long("fail_if_new_fingerprint")
+            .value_name("FILE")
+            .help("CI gate: fail if a fingerprint is missing from FILE,
+  one fingerprint (as printed) or query per line, # comments"))
+        .arg(Arg::with_name("max_fp_time")
+            .long("max_fp_time")
+            .value_name("SECONDS")
+            .help("CI gate: fail if total query time of a fingerprint
+  exceeds SECONDS"))
+        // No longer used, web mode reparses the log when it changes.
+        // Accepted for one more release not to break existing invocations
         .arg(Arg::with_name("wpd")
             .long("wpd")
             .value_name("MILLIS")
-            .help("Set queries background parse delay in web mode"))
-        .get_matches();
+            .hidden(true))
+        .get_matches_from_safe(join_lint_negations(env::args_os()))
+        .unwrap_or_else(|err| {
+            if !err.use_stderr() {
+                err.exit();
+            }
+
+            eprintln!("{}", err.message);
+            process::exit(EXIT_USAGE);
+        });
 
     cnf.log_file = matches.value_of("file").unwrap_or("mysql-slow.log").to_string();
+    cnf.state_file = matches.value_of("state_file").unwrap_or("").to_string();
 
-    if let Ok(ts_min) = matches.value_of("ts_min").unwrap_or("-1").parse::<i64>() {
-        cnf.timestamp_begin = ts_min;
-    } else {
-        let ts_min_date = matches.value_of("ts_min").unwrap_or("15/12/1901").to_string();
-        let datereg = Regex::new(r"^(?P<dd>\d{2})[/\-.](?P<mm>\d{2})[/\-.](?P<yyyy>\d{4})$").unwrap();
+    if let Some(timezone) = matches.value_of("timezone") {
+        match datetime::Zone::parse(timezone) {
+            Some(zone) => cnf.timezone = zone,
+            None => cnf.add_error("Timezone invalid")
+        }
+    }
 
-        if let Some(datecapts) = datereg.captures(&ts_min_date) {
-            let datestr = format!("{}/{}/{}:00:00:00",
-                                  &datecapts["dd"].to_string(),
-                                  &datecapts["mm"].to_string(),
-                                  &datecapts["yyyy"].to_string());
+    cnf.display_timezone = cnf.timezone.clone();
 
-            let date = Utc.datetime_from_str(&datestr, Query::DT_FORMAT).unwrap();
-            cnf.timestamp_begin = date.timestamp();
+    if let Some(display_timezone) = matches.value_of("display_timezone") {
+        match datetime::Zone::parse(display_timezone) {
+            Some(zone) => cnf.display_timezone = zone,
+            None => cnf.add_error("Display timezone invalid")
+        }
+    }
+
+    if let Some(dt_format) = matches.value_of("dt_format") {
+        if datetime::valid_format(dt_format) {
+            cnf.dt_format = dt_format.to_string();
         } else {
-            cnf.add_error("Timestamp range minimum value invalid syntax");
+            cnf.add_error("Date and time format invalid");
         }
     }
 
-    if let Ok(ts_max) = matches.value_of("ts_max").unwrap_or("-1").parse::<i64>() {
-        cnf.timestamp_end = if cnf.timestamp_begin > ts_max || ts_max < 0
-            { std::i64::MAX } else { ts_max };
-    } else {
-        let ts_max_date = matches.value_of("ts_max").unwrap_or("14/12/1901").to_string();
-        let datereg = Regex::new(r"^(?P<dd>\d{2})[/\-.](?P<mm>\d{2})[/\-.](?P<yyyy>\d{4})$").unwrap();
+    if let Some(ts_min) = matches.value_of("ts_min") {
+        match datetime::parse_timestamp(ts_min, &cnf.timezone, false) {
+            Some(timestamp) => cnf.timestamp_begin = timestamp,
+            None => cnf.add_error("Timestamp range minimum value invalid syntax")
+        }
+    }
 
-        if let Some(datecapts) = datereg.captures(&ts_max_date) {
-            let datestr = format!("{}/{}/{}:23:59:59",
-                                  &datecapts["dd"].to_string(),
-                                  &datecapts["mm"].to_string(),
-                                  &datecapts["yyyy"].to_string());
+    if let Some(ts_max) = matches.value_of("ts_max") {
+        match datetime::parse_timestamp(ts_max, &cnf.timezone, true) {
+            Some(timestamp) => cnf.timestamp_end = timestamp,
+            None => cnf.add_error("Timestamp range maximum value invalid syntax")
+        }
+    }
 
-            let date = Utc.datetime_from_str(&datestr, Query::DT_FORMAT).unwrap();
-            cnf.timestamp_end = date.timestamp();
+    if let Some(since) = matches.value_of("since") {
+        match datetime::parse_duration(since) {
+            Some(seconds) => cnf.timestamp_begin = cnf.timestamp_begin.max(datetime::now() - seconds),
+            None => cnf.add_error("Since value invalid syntax")
+        }
+    }
 
-            if cnf.timestamp_end < cnf.timestamp_begin {
-                cnf.timestamp_end = std::i64::MAX;
+    if let Some(until) = matches.value_of("until") {
+        match datetime::parse_duration(until) {
+            Some(seconds) => {
+                let timestamp_end = datetime::now() - seconds;
+
+                cnf.timestamp_end = if cnf.timestamp_end < 0 { timestamp_end } else { cnf.timestamp_end.min(timestamp_end) };
             }
-        } else {
-            cnf.add_error("Timestamp range maximum value invalid syntax");
+            None => cnf.add_error("Until value invalid syntax")
+        }
+    }
+
+    if let Some(last) = matches.value_of("last") {
+        match datetime::parse_duration(last) {
+            Some(seconds) => cnf.last = seconds,
+            None => cnf.add_error("Last value invalid syntax")
         }
     }
 
+    if cnf.timestamp_end < 0 || cnf.timestamp_end < cnf.timestamp_begin {
+        cnf.timestamp_end = std::i64::MAX;
+    }
+
     cnf.db = matches.value_of("database").unwrap_or("").to_string();
 
     if let Ok(qt_min) = matches.value_of("qt_min").unwrap_or("-1").parse::<f64>() {
@@ -387,171 +716,292 @@ Port 0 (zero) to disable feature (disabled by default)"))
         cnf.add_error("Limit value invalid syntax");
     }
 
-    if let Some(regex_string) = matches.value_of("query_regex") {
-        if let Ok(regex_value) = Regex::new(regex_string) {
-            cnf.regex = Some(regex_value);
-        } else {
-            cnf.add_error("Invalid query regex provided");
-        }
+    match compile_regexes(&matches, "query_regex", false) {
+        Ok(mut regexes) => cnf.regexes.append(&mut regexes),
+        Err(_) => cnf.add_error("Invalid query regex provided")
     }
 
-    if let Ok(wpd) = matches.value_of("wpd").unwrap_or("1").parse::<u64>() {
-        cnf.wpd = Duration::from_millis(wpd);
-    } else {
-        cnf.add_error("Web parse delay syntax error");
+    match compile_regexes(&matches, "query_iregex", true) {
+        Ok(mut regexes) => cnf.regexes.append(&mut regexes),
+        Err(_) => cnf.add_error("Invalid case-insensitive query regex provided")
     }
 
-    let web = matches.value_of("web").unwrap_or("0").to_string();
+    match compile_regexes(&matches, "exclude_regex", false) {
+        Ok(mut regexes) => cnf.exclude_regexes.append(&mut regexes),
+        Err(_) => cnf.add_error("Invalid exclude regex provided")
+    }
 
-    if web != "0" {
-        if let Some(addr_port_capts) = regs::addr_port(&web) {
-            cnf.web_addr = addr_port_capts["addr"].to_string();
-            cnf.web_port = addr_port_capts["port"].parse::<u16>().unwrap();
-        } else if let Ok(web_port) = web.parse::<u16>() {
-            cnf.web_addr = "127.0.0.1".to_string();
-            cnf.web_port = web_port;
-        } else {
-            cnf.add_error("Invalid web address:port pair provided");
-        }
+    match compile_regexes(&matches, "exclude_iregex", true) {
+        Ok(mut regexes) => cnf.exclude_regexes.append(&mut regexes),
+        Err(_) => cnf.add_error("Invalid case-insensitive exclude regex provided")
     }
 
-    cnf.abs = matches.occurrences_of("abstract") > 0;
-    cnf.dedup = matches.occurrences_of("dedup") > 0;
-
-    let sort_type = &*matches.value_of("sort_type").unwrap_or("ts").to_string();
-
-    cnf.sort_type = {
-        match sort_type {
-            "ts"   => QueriesSortType::Timestamp,
-            "qt"   => QueriesSortType::QueryTime,
-            "lt"   => QueriesSortType::LockTime,
-            "rs"   => QueriesSortType::RowsSent,
-            "re"   => QueriesSortType::RowsExamined,
-            "ra"   => QueriesSortType::RowsAffected,
-            "tsi"  => QueriesSortType::TimestampInverse,
-            "qti"  => QueriesSortType::QueryTimeInverse,
-            "lti"  => QueriesSortType::LockTimeInverse,
-            "rsi"  => QueriesSortType::RowsSentInverse,
-            "rei"  => QueriesSortType::RowsExaminedInverse,
-            "rai"  => QueriesSortType::RowsAffectedInverse,
-            "cnt"  => QueriesSortType::Count,
-            "cnti" => QueriesSortType::CountInverse,
-            _      => {
-                cnf.add_error("Sort type invalid");
-                QueriesSortType::Undefined
-            }
+    if matches.occurrences_of("ignore_noise") > 0 {
+        for pattern in regs::NOISE_PATTERNS.iter() {
+            cnf.exclude_regexes.push(RegexBuilder::new(pattern).case_insensitive(true).build().unwrap());
         }
-    };
+    }
 
-    let print_matches = matches.occurrences_of("print_cfg");
+    if let Some(tags) = matches.values_of("tag") {
+        for tag in tags {
+            let mut tag_split = tag.splitn(2, '=');
+            let key = tag_split.next().unwrap_or("").trim().to_string();
 
-    if print_matches > 0 {
-        println!("{}\n", cnf.to_string());
+            if key.len() == 0 {
+                cnf.add_error("Invalid comment tag filter provided");
+                continue;
+            }
 
-        if print_matches > 1 {
-            cnf.add_error("Interrupted by -pp flag. To disable interrupt, try -p flag");
+            cnf.tags.push((key, tag_split.next().map(|value| value.to_string())));
         }
     }
 
-    if cnf.has_errors() {
-        Err(cnf.errors())
-    } else {
-        Ok(())
+    if let Some(tables) = matches.values_of("table") {
+        for table in tables {
+            cnf.tables.push(table.replace("`", "").to_lowercase());
+        }
     }
-}
 
-fn handle_info(line: &String, query: &mut Query) {
-    if let Some(time) = regs::date_time(&line) {
-        let time_str = format!("{}/{}/{}:{}:{}:{}",
-            &time["day"], &time["month"], &time["year"],
-            &time["hour"], &time["minute"], &time["second"]);
+    if let Some(reports) = matches.values_of("report") {
+        for report in reports {
+            let report_type = match report {
+                "tables"      => ReportType::Tables,
+                "summary"     => ReportType::Summary,
+                "concurrency" => ReportType::Concurrency,
+                "locks"       => ReportType::Locks,
+                "sessions"    => ReportType::Sessions,
+                "nplus1"      => ReportType::NPlusOne,
+                _             => {
+                    cnf.add_error("Report type invalid");
+                    ReportType::Undefined
+                }
+            };
 
-        if let Ok(time) = Utc.datetime_from_str(&time_str, Query::DT_FORMAT) {
-            query.timestamp = time.timestamp();
+            cnf.reports.push(report_type);
         }
     }
 
-    if let Some(schema) = regs::schema(&line) {
-        query.db = schema["schema"].to_string();
+    match matches.value_of("burst_min").unwrap_or("10").parse::<usize>() {
+        Ok(burst_min) if burst_min > 1 => cnf.burst_min = burst_min,
+        _ => cnf.add_error("Burst minimum count invalid")
+    }
+
+    match matches.value_of("burst_window").unwrap_or("1").parse::<i64>() {
+        Ok(burst_window) if burst_window >= 0 => cnf.burst_window = burst_window,
+        _ => cnf.add_error("Burst window invalid, whole seconds expected")
     }
 
-    if let Some(query_time) = regs::query_time(&line) {
-        let query_time_str = query_time["query_time"].to_string();
+    cnf.save_baseline = matches.value_of("save_baseline").unwrap_or("").to_string();
+    cnf.baseline_file = matches.value_of("baseline").unwrap_or("").to_string();
 
-        if let Ok(query_time) = query_time_str.parse::<f64>() {
-            query.query_time = query_time;
+    if cnf.baseline_file.len() > 0 {
+        match Baseline::load(&cnf.baseline_file) {
+            Ok(baseline) => cnf.baseline = Some(baseline),
+            Err(err) => {
+                let err = format!("Can't load baseline file {}: {}", cnf.baseline_file, err);
+                cnf.add_error(&err);
+            }
         }
     }
 
-    if let Some(lock_time) = regs::lock_time(&line) {
-        let lock_time_str = lock_time["lock_time"].to_string();
+    match Threshold::parse(matches.value_of("anomaly").unwrap_or("3")) {
+        Some(anomaly) => cnf.anomaly = anomaly,
+        None => cnf.add_error("Anomaly threshold invalid")
+    }
 
-        if let Ok(lock_time) = lock_time_str.parse::<f64>() {
-            query.lock_time = lock_time;
+    if let Some(fail_if) = matches.values_of("fail_if") {
+        for expr_string in fail_if {
+            match expr::Expr::parse(expr_string) {
+                Ok(expr) => cnf.fail_if.push(expr),
+                Err(err) => cnf.add_error(&format!("Fail if expression invalid: {}", err))
+            }
         }
     }
 
-    if let Some(rows_sent) = regs::rows_sent(&line) {
-        let rows_sent_str = rows_sent["rows_sent"].to_string();
+    cnf.allowlist_file = matches.value_of("fail_if_new_fingerprint").unwrap_or("").to_string();
 
-        if let Ok(rows_sent) = rows_sent_str.parse::<i64>() {
-            query.rows_sent = rows_sent;
+    if cnf.allowlist_file.len() > 0 {
+        match gate::load_allowlist(&cnf.allowlist_file) {
+            Ok(allowlist) => cnf.allowlist = Some(allowlist),
+            Err(err) => {
+                let err = format!("Can't load fingerprint allowlist {}: {}", cnf.allowlist_file, err);
+                cnf.add_error(&err);
+            }
         }
     }
 
-    if let Some(rows_examined) = regs::rows_examined(&line) {
-        let rows_examined_str = rows_examined["rows_examined"].to_string();
+    if let Some(max_fp_time) = matches.value_of("max_fp_time") {
+        match max_fp_time.parse::<f64>() {
+            Ok(max_fp_time) if max_fp_time >= 0.0 => cnf.max_fp_time = max_fp_time,
+            _ => cnf.add_error("Max fingerprint time invalid")
+        }
+    }
 
-        if let Ok(rows_examined) = rows_examined_str.parse::<i64>() {
-            query.rows_examined = rows_examined;
+    if matches.is_present("lint") {
+        let names: Vec<&str> = matches.value_of("lint").unwrap_or("all").split(',').map(|name| name.trim()).collect();
+
+        if names.iter().all(|name| name.starts_with('-')) || names.contains(&"all") {
+            cnf.lint = LintRule::ALL.to_vec();
+        }
+
+        for name in names.iter().filter(|&&name| name != "all") {
+            match LintRule::parse(name.trim_start_matches('-')) {
+                Some(rule) if name.starts_with('-') => cnf.lint.retain(|enabled| *enabled != rule),
+                Some(rule) => if !cnf.lint.contains(&rule) { cnf.lint.push(rule) },
+                None => cnf.add_error(&format!("Lint rule invalid: {}", name))
+            }
         }
     }
 
-    if let Some(rows_affected) = regs::rows_affected(&line) {
-        let rows_affected_str = rows_affected["rows_affected"].to_string();
+    if let Some(group_by) = matches.value_of("group_by") {
+        for dim in group_by.split(',') {
+            let dim = dim.trim();
+
+            let group_dim = match dim {
+                "db"    => GroupDim::Db,
+                "user"  => GroupDim::User,
+                "host"  => GroupDim::Host,
+                "ip"    => GroupDim::Ip,
+                "stmt"  => GroupDim::Statement,
+                "table" => GroupDim::Table,
+                "hour"  => GroupDim::Hour,
+                _ if dim.starts_with("tag:") && dim.len() > 4 => GroupDim::Tag(dim[4..].to_string()),
+                _       => {
+                    cnf.add_error("Group by dimension invalid");
+                    GroupDim::Undefined
+                }
+            };
 
-        if let Ok(rows_affected) = rows_affected_str.parse::<i64>() {
-            query.rows_affected = rows_affected;
+            cnf.group_by.push(group_dim);
         }
     }
-}
 
-fn handle_raw(line: &String, query: &mut Query) {
-    let mut dirty = false;
+    if let Some(where_string) = matches.value_of("where") {
+        match expr::Expr::parse(where_string) {
+            Ok(where_expr) => cnf.where_expr = Some(where_expr),
+            Err(err) => cnf.add_error(&format!("Where expression invalid: {}", err))
+        }
+    }
 
-    if let Some(db) = regs::db(&line) {
-        query.db = db["db"].to_string();
-        dirty = true;
+    match matches.value_of("threads") {
+        Some(threads) => match threads.parse::<usize>() {
+            Ok(threads) if threads > 0 => cnf.threads = threads,
+            _ => cnf.add_error("Threads number invalid")
+        },
+        None => cnf.threads = num_cpus::get()
     }
 
-    if let Some(timestamp) = regs::timestamp(&line) {
-        let timestamp_str = timestamp["timestamp"].to_string();
+    let web = matches.value_of("web").unwrap_or("0").to_string();
 
-        if let Ok(timestamp) = timestamp_str.parse::<i64>() {
-            query.timestamp = timestamp;
+    if web != "0" {
+        if let Some(addr_port_capts) = regs::addr_port(&web) {
+            cnf.web_addr = addr_port_capts["addr"].to_string();
+            cnf.web_port = addr_port_capts["port"].parse::<u16>().unwrap();
+        } else if let Ok(web_port) = web.parse::<u16>() {
+            cnf.web_addr = "127.0.0.1".to_string();
+            cnf.web_port = web_port;
+        } else {
+            cnf.add_error("Invalid web address:port pair provided");
         }
+    }
+
+    cnf.abs = matches.occurrences_of("abstract") > 0;
+    if matches.is_present("dedup") {
+        cnf.dedup = match matches.value_of("dedup").unwrap_or("last") {
+            "slowest"  => Some(DedupKeep::Slowest),
+            "examined" => Some(DedupKeep::Examined),
+            "first"    => Some(DedupKeep::First),
+            "last"     => Some(DedupKeep::Last),
+            _          => {
+                cnf.add_error("Dedup keep invalid");
+                None
+            }
+        };
+    }
 
-        dirty = true;
+    let default_sort = if matches.is_present("aggregate") { "sum_qt:desc" } else { "ts" };
+
+    for key in matches.value_of("sort_type").unwrap_or(default_sort).split(',') {
+        match SortKey::parse(key) {
+            Some(sort_key) => cnf.sort.push(sort_key),
+            None => cnf.add_error(&format!("Sort key invalid: {}", key.trim()))
+        }
     }
 
-    if !dirty {
-        if !query.consuming_query {
-            query.query = String::new();
-            query.consuming_query = true;
+    let query_view = &*matches.value_of("query_view").unwrap_or("query").to_string();
+
+    cnf.query_view = {
+        match query_view {
+            "query"  => QueryView::Query,
+            "raw"    => QueryView::Raw,
+            "fp"     => QueryView::Fingerprint,
+            "sample" => QueryView::Sample,
+            "both"   => QueryView::Both,
+            _        => {
+                cnf.add_error("Query view invalid");
+                QueryView::Undefined
+            }
         }
+    };
 
-        query.query += &*regs::remove_comments(line);
+    cnf.format = match matches.value_of("format").unwrap_or("text") {
+        "text" => OutputFormat::Text,
+        "json" => OutputFormat::Json,
+        _ => {
+            cnf.add_error("Output format invalid");
+            OutputFormat::Text
+        }
+    };
+
+    cnf.aggregate = matches.is_present("aggregate");
+
+    if cnf.aggregate && (cnf.last > 0 || cnf.reports.len() > 0 || cnf.group_by.len() > 0 || cnf.dedup.is_some() || cnf.web_port > 0 ||
+        cnf.save_baseline.len() > 0 || cnf.baseline.is_some() || cnf.gate()) {
+        cnf.add_error("Aggregate mode can't be combined with --last, --report, --group_by, --dedup, --web, --save_baseline, --baseline and CI gate checks");
     }
 
-    if query.consuming_query && regs::is_query_end(line) {
-        let cnf = config.lock().unwrap();
+    if cnf.json() && (cnf.aggregate || cnf.reports.len() > 0 || cnf.group_by.len() > 0 ||
+        cnf.baseline.is_some() || cnf.gate() || cnf.web_port > 0) {
+        cnf.add_error("JSON output can't be combined with --aggregate, --report, --group_by, --baseline, --web and CI gate checks");
+    }
 
-        if cnf.abs {
-            query.query = regs::abs_numbers(&query.query);
-            query.query = regs::abs_strings(&query.query);
+    if cnf.gate() && cnf.web_port > 0 {
+        cnf.add_error("CI gate checks can't be combined with --web");
+    }
+
+    // Web refreshes parse the whole log, so the first page would differ from the next ones
+    if cnf.state_file.len() > 0 && cnf.web_port > 0 {
+        cnf.add_error("State file can't be combined with --web");
+    }
+
+    let print_matches = matches.occurrences_of("print_cfg");
+
+    if print_matches > 0 {
+        println!("{}\n", cnf.to_string());
+
+        if print_matches > 1 {
+            cnf.add_error("Interrupted by -pp flag. To disable interrupt, try -p flag");
         }
+    }
 
-        query.query = regs::prs_spaces_trim(&query.query);
-        query.query_consumed = true;
+    if cnf.has_errors() {
+        Err(cnf.errors())
+    } else {
+        Ok(cnf)
+    }
+}
+
+fn compile_regexes(matches: &ArgMatches, name: &str, case_insensitive: bool) -> Result<Vec<Regex>, ()> {
+    let mut regexes: Vec<Regex> = Vec::new();
+
+    if let Some(regex_strings) = matches.values_of(name) {
+        for regex_string in regex_strings {
+            match RegexBuilder::new(regex_string).case_insensitive(case_insensitive).build() {
+                Ok(regex_value) => regexes.push(regex_value),
+                Err(_) => return Err(())
+            }
+        }
     }
+
+    Ok(regexes)
 }
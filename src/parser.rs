@@ -0,0 +1,537 @@
+use types::{Query, EXTENDED_FIELDS};
+use datetime::Zone;
+use regs;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::mem;
+use std::thread;
+use chrono::NaiveDateTime;
+
+/// Smaller logs are not worth splitting between threads
+const MIN_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+const CARRY_BLOCK_SIZE: u64 = 64 * 1024;
+
+pub struct Parser {
+    abs: bool,
+    zone: Zone,
+    query: Query,
+    pub db: String,
+    pub timestamp: i64
+}
+
+impl Parser {
+    pub fn new(abs: bool, zone: Zone, db: String, timestamp: i64) -> Self {
+        Self {
+            abs,
+            zone,
+            query: Query::new(),
+            db,
+            timestamp
+        }
+    }
+
+    /// Feeds one log line without line feed, returns entry when it is finished
+    pub fn line(&mut self, line: &String) -> Option<Query> {
+        if regs::is_info(line) {
+            handle_info(line, &mut self.query, &self.zone);
+        } else {
+            handle_raw(line, &mut self.query, self.abs);
+        }
+
+        if !self.query.valid() {
+            return None;
+        }
+
+        let mut query = mem::replace(&mut self.query, Query::new());
+
+        if query.db == "?" {
+            query.db = self.db.clone();
+        } else {
+            self.db = query.db.clone();
+        }
+
+        if query.timestamp < 0 {
+            query.timestamp = self.timestamp;
+        } else {
+            self.timestamp = query.timestamp;
+        }
+
+        query.finish();
+
+        Some(query)
+    }
+}
+
+pub struct Parsed<A> {
+    pub results: Vec<A>,
+    pub consumed: u64,
+    pub server_info: String,
+    pub db: String,
+    pub timestamp: i64
+}
+
+struct Chunk<A> {
+    result: A,
+    consumed: u64,
+    server_info: String,
+    db: String,
+    timestamp: i64
+}
+
+/// Offset of the first entry starting after pos. Entries begin with "# Time:"
+/// or, in logs written without it, with "# User@Host:". Only entries right after
+/// a finished statement are taken, so a chunk starts in the same parser state
+/// as sequential parsing would have there.
+fn next_entry<R: BufRead + Seek>(reader: &mut R, pos: u64, size: u64) -> io::Result<u64> {
+    let mut line: Vec<u8> = Vec::new();
+
+    reader.seek(SeekFrom::Start(pos))?;
+
+    let mut offset = pos + reader.read_until(b'\n', &mut line)? as u64;
+    let mut prev_end = false;
+
+    loop {
+        line.clear();
+        let len = reader.read_until(b'\n', &mut line)? as u64;
+
+        if len == 0 {
+            return Ok(size);
+        }
+
+        if prev_end && (line.starts_with(b"# Time:") || line.starts_with(b"# User@Host:")) {
+            return Ok(offset);
+        }
+
+        let text = String::from_utf8_lossy(&line[..line.len() - 1]).into_owned();
+
+        prev_end = line.ends_with(b"\n") && !regs::is_info(&text) && regs::is_query_end(&text) &&
+            regs::db(&text).is_none() && regs::timestamp(&text).is_none();
+        offset += len;
+    }
+}
+
+fn chunk_bounds(path: &String, start: u64, size: u64, threads: usize, min_chunk_size: u64) -> io::Result<Vec<u64>> {
+    let mut bounds: Vec<u64> = vec![start];
+    let chunks = (size.saturating_sub(start) / min_chunk_size).max(1).min(threads.max(1) as u64);
+
+    if chunks > 1 {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        for index in 1..chunks {
+            let pos = start + (size - start) * index / chunks;
+
+            if pos <= *bounds.last().unwrap() {
+                continue;
+            }
+
+            let bound = next_entry(&mut reader, pos, size)?;
+
+            if bound > *bounds.last().unwrap() && bound < size {
+                bounds.push(bound);
+            }
+        }
+    }
+
+    bounds.push(size);
+
+    Ok(bounds)
+}
+
+/// Last database and timestamp set between start and end, found by reading
+/// lines backward from end, so usually only a few blocks are read.
+fn last_carried(path: &String, start: u64, end: u64, zone: &Zone) -> io::Result<(Option<String>, Option<i64>)> {
+    let mut file = File::open(path)?;
+    let (mut db, mut timestamp): (Option<String>, Option<i64>) = (None, None);
+    let mut tail: Vec<u8> = Vec::new();
+    let mut block_end = end;
+
+    while block_end > start && (db.is_none() || timestamp.is_none()) {
+        let block_start = block_end.saturating_sub(CARRY_BLOCK_SIZE).max(start);
+        let mut block = vec![0; (block_end - block_start) as usize];
+
+        file.seek(SeekFrom::Start(block_start))?;
+        file.read_exact(&mut block)?;
+        block.extend_from_slice(&tail);
+
+        let mut lines: Vec<&[u8]> = block.split(|byte| *byte == b'\n').collect();
+
+        tail = if block_start > start { lines.remove(0).to_vec() } else { Vec::new() };
+
+        for line in lines.iter().rev() {
+            let line = String::from_utf8_lossy(line).into_owned();
+            let mut query = Query::new();
+
+            if regs::is_info(&line) {
+                handle_info(&line, &mut query, zone);
+            } else {
+                handle_raw(&line, &mut query, false);
+            }
+
+            if db.is_none() && query.db != "?" {
+                db = Some(query.db);
+            }
+
+            if timestamp.is_none() && query.timestamp >= 0 {
+                timestamp = Some(query.timestamp);
+            }
+
+            if db.is_some() && timestamp.is_some() {
+                break;
+            }
+        }
+
+        block_end = block_start;
+    }
+
+    Ok((db, timestamp))
+}
+
+/// Database and timestamp carried into every chunk, as sequential parsing
+/// would have them at its start
+fn carry_in(path: &String, bounds: &Vec<u64>, zone: &Zone, db: String, timestamp: i64) -> io::Result<Vec<(String, i64)>> {
+    let mut handles = Vec::new();
+
+    for window in bounds.windows(2).take(bounds.len().saturating_sub(2)) {
+        let (path, start, end, zone) = (path.clone(), window[0], window[1], zone.clone());
+
+        handles.push(thread::spawn(move || last_carried(&path, start, end, &zone)));
+    }
+
+    let mut carried = vec![(db, timestamp)];
+
+    for handle in handles {
+        let (db, timestamp) = match handle.join() {
+            Ok(last) => last?,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "parser thread panicked"))
+        };
+
+        let prev = carried.last().unwrap().clone();
+
+        carried.push((db.unwrap_or(prev.0), timestamp.unwrap_or(prev.1)));
+    }
+
+    Ok(carried)
+}
+
+fn parse_chunk<A, F>(path: &String, start: u64, end: u64, abs: bool, zone: Zone, db: String, timestamp: i64, init: A, fold: &F) -> io::Result<Chunk<A>>
+    where F: Fn(&mut A, Query) {
+    let mut file = File::open(path)?;
+
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut reader = BufReader::new(file.take(end - start));
+    let mut parser = Parser::new(abs, zone, db, timestamp);
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut chunk = Chunk {
+        result: init,
+        consumed: start,
+        server_info: String::new(),
+        db: String::new(),
+        timestamp: -1
+    };
+    let mut server_info_consumed = start > 0;
+    let mut position = start;
+
+    loop {
+        bytes.clear();
+        let len = reader.read_until(b'\n', &mut bytes)?;
+
+        if len == 0 || bytes[len - 1] != b'\n' {
+            break;
+        }
+
+        position += len as u64;
+        let line = String::from_utf8_lossy(&bytes[..len - 1]).into_owned();
+
+        if !server_info_consumed {
+            if !regs::is_info(&line) {
+                chunk.server_info += &line;
+                chunk.server_info.push_str("\n");
+                continue;
+            } else {
+                server_info_consumed = true;
+            }
+        }
+
+        if let Some(query) = parser.line(&line) {
+            if regs::is_query_end(&query.query) {
+                fold(&mut chunk.result, query);
+            }
+
+            chunk.consumed = position;
+        }
+    }
+
+    chunk.db = parser.db;
+    chunk.timestamp = parser.timestamp;
+
+    Ok(chunk)
+}
+
+/// Parses log from offset, splitting it by entries between threads. Every thread
+/// folds finished entries of its chunk into own copy of init, results are returned
+/// in log order. Database and timestamp are carried over chunk boundaries
+/// as in sequential parsing.
+pub fn fold_file<A, F>(path: &String, offset: u64, threads: usize, abs: bool, zone: &Zone, db: String, timestamp: i64, init: &A, fold: F) -> io::Result<Parsed<A>>
+    where A: Clone + Send + 'static, F: Fn(&mut A, Query) + Clone + Send + 'static {
+    let size = File::open(path)?.metadata()?.len();
+    let bounds = chunk_bounds(path, offset, size, threads, MIN_CHUNK_SIZE)?;
+
+    fold_chunks(path, &bounds, abs, zone, db, timestamp, init, fold)
+}
+
+/// Parses chunks between consecutive bounds in threads
+fn fold_chunks<A, F>(path: &String, bounds: &Vec<u64>, abs: bool, zone: &Zone, db: String, timestamp: i64, init: &A, fold: F) -> io::Result<Parsed<A>>
+    where A: Clone + Send + 'static, F: Fn(&mut A, Query) + Clone + Send + 'static {
+    let carried = carry_in(path, bounds, zone, db.clone(), timestamp)?;
+    let mut handles = Vec::new();
+
+    for (window, (db, timestamp)) in bounds.windows(2).zip(carried.into_iter()) {
+        let (path, start, end) = (path.clone(), window[0], window[1]);
+        let (init, fold, zone) = (init.clone(), fold.clone(), zone.clone());
+
+        handles.push(thread::spawn(move || parse_chunk(&path, start, end, abs, zone, db, timestamp, init, &fold)));
+    }
+
+    let mut parsed = Parsed {
+        results: Vec::new(),
+        consumed: bounds[0],
+        server_info: String::new(),
+        db,
+        timestamp
+    };
+
+    for handle in handles {
+        let chunk = match handle.join() {
+            Ok(chunk) => chunk?,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "parser thread panicked"))
+        };
+
+        parsed.consumed = parsed.consumed.max(chunk.consumed);
+        parsed.server_info.push_str(&chunk.server_info);
+        parsed.results.push(chunk.result);
+        parsed.db = chunk.db;
+        parsed.timestamp = chunk.timestamp;
+    }
+
+    Ok(parsed)
+}
+
+/// Old style "# Time: YYMMDD HH:MM:SS" stamps are server local time, taken in zone
+fn handle_info(line: &String, query: &mut Query, zone: &Zone) {
+    if let Some(time) = regs::date_time(&line) {
+        let time_str = format!("{}/{}/20{}:{}:{}:{}",
+            &time["day"], &time["month"], &time["year"],
+            &time["hour"], &time["minute"], &time["second"]);
+
+        if let Some(timestamp) = NaiveDateTime::parse_from_str(&time_str, Query::DT_FORMAT).ok()
+            .and_then(|time| zone.local_timestamp(&time)) {
+            query.timestamp = timestamp;
+        }
+    }
+
+    if let Some(schema) = regs::schema(&line) {
+        query.db = schema["schema"].to_string();
+    }
+
+    for (name, value) in regs::info_fields(line) {
+        let name = name.to_lowercase();
+
+        if name == "thread_id" && query.connection_id < 0 {
+            query.connection_id = value.parse::<i64>().unwrap_or(-1);
+        }
+
+        if EXTENDED_FIELDS.contains(&&*name) {
+            query.extended.insert(name, value);
+        }
+    }
+
+    if let Some(user_host) = regs::user_host(&line) {
+        query.user = user_host["user"].trim().to_string();
+        query.host = user_host["host"].to_string();
+        query.ip = user_host["ip"].to_string();
+
+        if let Some(id) = user_host.name("id") {
+            query.connection_id = id.as_str().parse::<i64>().unwrap_or(-1);
+        }
+    }
+
+    if let Some(query_time) = regs::query_time(&line) {
+        let query_time_str = query_time["query_time"].to_string();
+
+        if let Ok(query_time) = query_time_str.parse::<f64>() {
+            query.query_time = query_time;
+        }
+    }
+
+    if let Some(lock_time) = regs::lock_time(&line) {
+        let lock_time_str = lock_time["lock_time"].to_string();
+
+        if let Ok(lock_time) = lock_time_str.parse::<f64>() {
+            query.lock_time = lock_time;
+        }
+    }
+
+    if let Some(rows_sent) = regs::rows_sent(&line) {
+        let rows_sent_str = rows_sent["rows_sent"].to_string();
+
+        if let Ok(rows_sent) = rows_sent_str.parse::<i64>() {
+            query.rows_sent = rows_sent;
+        }
+    }
+
+    if let Some(rows_examined) = regs::rows_examined(&line) {
+        let rows_examined_str = rows_examined["rows_examined"].to_string();
+
+        if let Ok(rows_examined) = rows_examined_str.parse::<i64>() {
+            query.rows_examined = rows_examined;
+        }
+    }
+
+    if let Some(rows_affected) = regs::rows_affected(&line) {
+        let rows_affected_str = rows_affected["rows_affected"].to_string();
+
+        if let Ok(rows_affected) = rows_affected_str.parse::<i64>() {
+            query.rows_affected = rows_affected;
+        }
+    }
+}
+
+fn handle_raw(line: &String, query: &mut Query, abs: bool) {
+    let mut dirty = false;
+
+    if let Some(db) = regs::db(&line) {
+        query.db = db["db"].to_string();
+        dirty = true;
+    }
+
+    if let Some(timestamp) = regs::timestamp(&line) {
+        let timestamp_str = timestamp["timestamp"].to_string();
+
+        if let Ok(timestamp) = timestamp_str.parse::<i64>() {
+            query.timestamp = timestamp;
+        }
+
+        dirty = true;
+    }
+
+    if !dirty {
+        if !query.consuming_query {
+            query.query = String::new();
+            query.raw_query = String::new();
+            query.consuming_query = true;
+        }
+
+        query.raw_query += line;
+        query.raw_query.push_str("\n");
+    }
+
+    if query.consuming_query && regs::is_query_end(line) {
+        // Comments are handled on the whole query, they may span lines
+        for comment in regs::comments(&query.raw_query) {
+            if comment.starts_with('+') {
+                query.hints.push(comment[1..].trim().to_string());
+            } else {
+                for (key, value) in regs::comment_tags(&comment) {
+                    query.tags.insert(key, value);
+                }
+            }
+        }
+
+        query.query = regs::remove_comments(&query.raw_query);
+        query.fingerprint = Query::fingerprint_of(&query.raw_query);
+
+        if abs {
+            query.query = query.fingerprint.clone();
+        } else {
+            query.query = regs::prs_spaces_trim(&query.query);
+        }
+
+        query.query_consumed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Write;
+
+    /// Log where only some entries set database and timestamp, so later ones take them from earlier
+    fn write_log() -> String {
+        let path = env::temp_dir().join(format!("myslowparser-chunks-{}.log", ::std::process::id()));
+        let mut file = File::create(&path).unwrap();
+
+        write!(file, "/usr/sbin/mysqld, Version: 5.7.23-log (MySQL Community Server (GPL)). started with:\n\
+                      Tcp port: 3306  Unix socket: /var/run/mysqld/mysqld.sock\n\
+                      Time                 Id Command    Argument\n").unwrap();
+
+        for index in 0..120 {
+            if index % 5 == 0 {
+                writeln!(file, "# Time: 180912 10:{:02}:{:02}", index / 60, index % 60).unwrap();
+            }
+
+            write!(file, "# User@Host: app[app] @ web1 [10.0.0.5]  Id:    {}\n\
+                          # Query_time: {}.000000  Lock_time: 0.000000 Rows_sent: 1  Rows_examined: 10\n", index % 3, index).unwrap();
+
+            if index % 7 == 0 {
+                writeln!(file, "use db{};", index).unwrap();
+            }
+
+            if index % 3 == 0 {
+                writeln!(file, "SET timestamp={};", 1_536_746_400 + index).unwrap();
+            }
+
+            if index % 4 == 1 {
+                write!(file, "SELECT *\n  FROM t{}\n  WHERE id = {};\n", index % 4, index).unwrap();
+            } else {
+                writeln!(file, "SELECT * FROM t{} WHERE id = {};", index % 4, index).unwrap();
+            }
+        }
+
+        path.to_str().unwrap().to_string()
+    }
+
+    fn parse(path: &String, bounds: &Vec<u64>) -> (Vec<String>, u64, String, String, i64) {
+        let fold = |entries: &mut Vec<String>, q: Query|
+            entries.push(format!("{} {} {} {}", q.db, q.timestamp, q.query_time, q.raw_query));
+        let parsed = fold_chunks(path, bounds, false, &Zone::utc(), "?".to_string(), -1, &Vec::new(), fold).unwrap();
+        let entries = parsed.results.into_iter().flat_map(|entries| entries.into_iter()).collect();
+
+        (entries, parsed.consumed, parsed.server_info, parsed.db, parsed.timestamp)
+    }
+
+    #[test]
+    fn chunks_parse_as_sequential() {
+        let path = write_log();
+        let size = File::open(&path).unwrap().metadata().unwrap().len();
+        let sequential = parse(&path, &vec![0, size]);
+
+        assert_eq!(sequential.0.len(), 120);
+        assert_eq!(sequential.1, size);
+        assert!(sequential.0[1].starts_with("db0 1536746400 1 "));
+        assert!(sequential.2.starts_with("/usr/sbin/mysqld"));
+
+        // Every entry boundary, taken as the only split
+        let mut reader = BufReader::new(File::open(&path).unwrap());
+        let mut bounds: Vec<u64> = (0..size).map(|pos| next_entry(&mut reader, pos, size).unwrap())
+            .filter(|bound| *bound > 0 && *bound < size)
+            .collect();
+
+        bounds.dedup();
+        assert_eq!(bounds.len(), 119);
+
+        for bound in bounds {
+            assert_eq!(parse(&path, &vec![0, bound, size]), sequential, "split at {}", bound);
+        }
+
+        // Several chunks at once, as fold_file splits large logs
+        for &threads in [3, 8, 64].iter() {
+            let bounds = chunk_bounds(&path, 0, size, threads, 256).unwrap();
+
+            assert_eq!(bounds.len(), threads.min(119) + 1);
+            assert_eq!(parse(&path, &bounds), sequential, "threads {}", threads);
+        }
+    }
+}
@@ -1,198 +1,434 @@
-use types::Query;
-use super::config;
-use types::QueriesSortType;
-use std::collections::HashMap;
-use web::wqq;
-use std::sync::Mutex;
-use std::thread::sleep;
+use types::{Query, Config};
+use types::{SortKey, SortField, QueryView, DedupKeep};
+use std::collections::{HashMap, BinaryHeap};
+use std::cmp::Ordering;
+use std::usize;
+use reports;
+use stats;
+use lint::Findings;
+use baseline::{self, Baseline};
+use gate::Verdict;
+use groups;
+use json;
+
+/// Aggregates of the same queries, as counted for COUNT
+#[derive(Clone)]
+pub struct Totals {
+    pub count: usize,
+    pub query_time: f64,
+    pub max_query_time: f64,
+    pub rows_sent: i64,
+    pub rows_examined: i64
+}
+
+impl Totals {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            query_time: 0.0,
+            max_query_time: 0.0,
+            rows_sent: 0,
+            rows_examined: 0
+        }
+    }
+
+    fn add(&mut self, q: &Query) {
+        self.count += 1;
+
+        if q.query_time >= 0.0 {
+            self.query_time += q.query_time;
+            self.max_query_time = self.max_query_time.max(q.query_time);
+        }
+
+        if q.rows_sent >= 0 {
+            self.rows_sent += q.rows_sent;
+        }
+
+        if q.rows_examined >= 0 {
+            self.rows_examined += q.rows_examined;
+        }
+    }
+}
 
-lazy_static! {
-    pub static ref qhash: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+/// Result of one processing run. It is never changed after creation,
+/// so the web server shares it between requests and replaces it as a whole.
+pub struct Snapshot {
+    /// Every query passing filters, reports see duplicates too
+    pub queries: Vec<Query>,
+    /// Indices of listed queries, one per fingerprint when deduplicated
+    pub order: Vec<usize>,
+    pub totals: HashMap<String, Totals>,
+    pub samples: HashMap<String, String>,
+    pub groups: HashMap<String, Totals>,
+    pub findings: Findings,
+    pub profile: Option<Baseline>,
+    pub verdict: Option<Verdict>,
+    pub total: usize,
+    pub filtered_out: usize,
+    pub duplicates: usize
 }
 
-fn sort(qq: &mut Vec<Query>) {
-    let cnf = config.lock().unwrap();
+impl Snapshot {
+    pub fn count(&self, q: &Query) -> usize {
+        self.totals.get(&q.query).map(|totals| totals.count).unwrap_or(1)
+    }
+
+    pub fn sample(&self, q: &Query) -> Option<&String> {
+        self.samples.get(&q.fingerprint)
+    }
+
+    /// Queries to show, sorted and limited
+    pub fn shown(&self) -> Vec<&Query> {
+        self.order.iter().map(|&index| &self.queries[index]).collect()
+    }
+
+    /// Query entry, with stats of its fingerprint group when deduplicated
+    pub fn render(&self, index: usize, q: &Query, cnf: &Config) -> String {
+        let mut buf = q.header(index, self.count(q), cnf);
 
-    match cnf.sort_type {
-        QueriesSortType::Timestamp =>
-            qq.sort_by(|lhs, rhs|
-                lhs.timestamp.partial_cmp(&rhs.timestamp).unwrap()),
+        if let Some(group) = self.groups.get(&q.fingerprint) {
+            buf.push_str(&format!("\n>>>> GROUP_COUNT: {} | TOTAL_TIME: {:.6} | AVG_TIME: {:.6} | MAX_TIME: {:.6} | ROWS_EXAMINED: {}",
+                                  group.count, group.query_time, group.query_time / group.count as f64,
+                                  group.max_query_time, group.rows_examined));
+        }
 
-        QueriesSortType::QueryTime =>
-            qq.sort_by(|lhs, rhs|
-                lhs.query_time.partial_cmp(&rhs.query_time).unwrap()),
+        buf.push_str(&format!("\n{}\n", q.query_text(&cnf.query_view, self.sample(q))));
 
-        QueriesSortType::LockTime =>
-            qq.sort_by(|lhs, rhs|
-                lhs.lock_time.partial_cmp(&rhs.lock_time).unwrap()),
+        buf
+    }
+}
 
-        QueriesSortType::RowsSent =>
-            qq.sort_by(|lhs, rhs|
-                lhs.rows_sent.partial_cmp(&rhs.rows_sent).unwrap()),
+/// Value of one sort key. Missing values are lower than any other.
+#[derive(PartialEq, PartialOrd)]
+pub enum SortValue {
+    Null,
+    Num(f64),
+    Str(String)
+}
 
-        QueriesSortType::RowsExamined =>
-            qq.sort_by(|lhs, rhs|
-                lhs.rows_examined.partial_cmp(&rhs.rows_examined).unwrap()),
+/// Compares values of all sort keys in order until they differ
+pub fn compare_values(lhs: &Vec<SortValue>, rhs: &Vec<SortValue>, keys: &Vec<SortKey>) -> Ordering {
+    for ((lhs, rhs), key) in lhs.iter().zip(rhs.iter()).zip(keys.iter()) {
+        let ordering = lhs.partial_cmp(rhs).unwrap_or(Ordering::Equal);
 
-        QueriesSortType::RowsAffected =>
-            qq.sort_by(|lhs, rhs|
-                lhs.rows_affected.partial_cmp(&rhs.rows_affected).unwrap()),
+        if ordering != Ordering::Equal {
+            return if key.descending { ordering.reverse() } else { ordering };
+        }
+    }
 
-        QueriesSortType::TimestampInverse =>
-            qq.sort_by(|lhs, rhs|
-                rhs.timestamp.partial_cmp(&lhs.timestamp).unwrap()),
+    Ordering::Equal
+}
 
-        QueriesSortType::QueryTimeInverse =>
-            qq.sort_by(|lhs, rhs|
-                rhs.query_time.partial_cmp(&lhs.query_time).unwrap()),
+/// totals are of the same queries, fingerprint of the same fingerprint
+fn sort_value(q: &Query, field: &SortField, totals: &Totals, fingerprint: &Totals) -> SortValue {
+    match field {
+        &SortField::Timestamp => SortValue::Num(q.timestamp as f64),
+        &SortField::QueryTime => SortValue::Num(q.query_time),
+        &SortField::LockTime => SortValue::Num(q.lock_time),
+        &SortField::RowsSent => SortValue::Num(q.rows_sent as f64),
+        &SortField::RowsExamined => SortValue::Num(q.rows_examined as f64),
+        &SortField::RowsAffected => SortValue::Num(q.rows_affected as f64),
+        &SortField::Db => SortValue::Str(q.db.clone()),
+        &SortField::User => SortValue::Str(q.user.clone()),
+        &SortField::Host => SortValue::Str(q.host.clone()),
+        &SortField::Ip => SortValue::Str(q.ip.clone()),
+        &SortField::Statement => SortValue::Str(q.statement.clone()),
+        &SortField::Fingerprint => SortValue::Str(q.fingerprint.clone()),
+        &SortField::Tag(ref key) => q.tags.get(key).map(|value| SortValue::Str(value.clone())).unwrap_or(SortValue::Null),
+        &SortField::Extended(ref name) => match (q.extended_value(name), q.extended.get(name)) {
+            (Some(number), _) => SortValue::Num(number),
+            (None, Some(value)) => SortValue::Str(value.clone()),
+            (None, None) => SortValue::Null
+        },
+        &SortField::Count => SortValue::Num(totals.count as f64),
+        &SortField::TotalTime => SortValue::Num(totals.query_time),
+        &SortField::AvgTime => SortValue::Num(totals.query_time / totals.count.max(1) as f64),
+        &SortField::MaxTime => SortValue::Num(totals.max_query_time),
+        &SortField::TotalExamined => SortValue::Num(totals.rows_examined as f64),
+        &SortField::ExaminedRatio => SortValue::Num(stats::examined_ratio(fingerprint.rows_examined, fingerprint.rows_sent))
+    }
+}
 
-        QueriesSortType::LockTimeInverse =>
-            qq.sort_by(|lhs, rhs|
-                rhs.lock_time.partial_cmp(&lhs.lock_time).unwrap()),
+/// Position of query in the output. Queries with equal sort values are
+/// ordered by timestamp, then by position in the log.
+struct Ranked<'a> {
+    values: Vec<SortValue>,
+    keys: &'a Vec<SortKey>,
+    timestamp: i64,
+    index: usize
+}
 
-        QueriesSortType::RowsSentInverse =>
-            qq.sort_by(|lhs, rhs|
-                rhs.rows_sent.partial_cmp(&lhs.rows_sent).unwrap()),
+impl<'a> PartialEq for Ranked<'a> {
+    fn eq(&self, other: &Ranked) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
 
-        QueriesSortType::RowsExaminedInverse =>
-            qq.sort_by(|lhs, rhs|
-                rhs.rows_examined.partial_cmp(&lhs.rows_examined).unwrap()),
+impl<'a> Eq for Ranked<'a> {}
 
-        QueriesSortType::RowsAffectedInverse =>
-            qq.sort_by(|lhs, rhs|
-                rhs.rows_affected.partial_cmp(&lhs.rows_affected).unwrap()),
+impl<'a> PartialOrd for Ranked<'a> {
+    fn partial_cmp(&self, other: &Ranked) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
-        _ => {}
+impl<'a> Ord for Ranked<'a> {
+    fn cmp(&self, other: &Ranked) -> Ordering {
+        compare_values(&self.values, &other.values, self.keys)
+            .then_with(|| self.timestamp.cmp(&other.timestamp))
+            .then_with(|| self.index.cmp(&other.index))
     }
 }
 
-fn filter(qq: &Vec<Query>, mapflt: &mut usize) -> Vec<Query> {
-    let cnf = config.lock().unwrap();
+/// Indices of listed queries in output order. When limit is set, only the first
+/// limit queries are selected with a bounded heap instead of sorting them all.
+fn rank(qq: &Vec<Query>, listed: &Vec<usize>, cnf: &Config, totals: &HashMap<String, Totals>, fingerprints: &HashMap<String, Totals>) -> Vec<usize> {
+    let shown = if cnf.limit < usize::MAX { (cnf.limit + 1).min(listed.len()) } else { listed.len() };
 
-    qq.into_iter().filter(|q| {
-        let not_filtered = q.timestamp >= cnf.timestamp_begin &&
-            q.timestamp < cnf.timestamp_end &&
-            q.query_time >= cnf.query_time_min &&
-            q.query_time < cnf.query_time_max &&
-            q.lock_time >= cnf.lock_time_min &&
-            q.lock_time < cnf.lock_time_max &&
-            q.rows_sent >= cnf.rows_sent_min &&
-            q.rows_sent < cnf.rows_sent_max &&
-            q.rows_examined >= cnf.rows_examined_min &&
-            q.rows_examined < cnf.rows_examined_max &&
-            q.rows_affected >= cnf.rows_affected_min &&
-            q.rows_affected < cnf.rows_affected_max;
-
-        if not_filtered {
-            if let Some(regex) = &cnf.regex {
-                let not_filter = !regex.find(&q.query).is_none();
-
-                if !not_filter {
-                    *mapflt += 1;
-                }
-
-                return not_filter;
+    if cnf.sort.len() == 0 {
+        return listed.iter().take(shown).cloned().collect();
+    }
+
+    let no_totals = Totals::new();
+
+    let ranked = listed.iter().map(|&index| {
+        let q = &qq[index];
+        let q_totals = totals.get(&q.query).unwrap_or(&no_totals);
+        let fp_totals = fingerprints.get(&q.fingerprint).unwrap_or(&no_totals);
+
+        Ranked {
+            values: cnf.sort.iter().map(|key| sort_value(q, &key.field, q_totals, fp_totals)).collect(),
+            keys: &cnf.sort,
+            timestamp: q.timestamp,
+            index
+        }
+    });
+
+    let mut top: Vec<Ranked> = if shown < listed.len() {
+        let mut heap: BinaryHeap<Ranked> = BinaryHeap::with_capacity(shown + 1);
+
+        for item in ranked {
+            if heap.len() < shown {
+                heap.push(item);
+            } else if item < *heap.peek().unwrap() {
+                heap.pop();
+                heap.push(item);
             }
         }
 
+        heap.into_vec()
+    } else {
+        ranked.collect()
+    };
+
+    top.sort();
+    top.into_iter().map(|item| item.index).collect()
+}
+
+/// Whether query passes all filters, count range aside
+pub fn matches(q: &Query, cnf: &Config, timestamp_floor: i64) -> bool {
+    q.timestamp >= cnf.timestamp_begin &&
+        q.timestamp >= timestamp_floor &&
+        q.timestamp < cnf.timestamp_end &&
+        q.query_time >= cnf.query_time_min &&
+        q.query_time < cnf.query_time_max &&
+        q.lock_time >= cnf.lock_time_min &&
+        q.lock_time < cnf.lock_time_max &&
+        q.rows_sent >= cnf.rows_sent_min &&
+        q.rows_sent < cnf.rows_sent_max &&
+        q.rows_examined >= cnf.rows_examined_min &&
+        q.rows_examined < cnf.rows_examined_max &&
+        q.rows_affected >= cnf.rows_affected_min &&
+        q.rows_affected < cnf.rows_affected_max &&
+        cnf.tags.iter().all(|&(ref key, ref value)| q.has_tag(key, value)) &&
+        (cnf.tables.len() == 0 || cnf.tables.iter().any(|table| q.has_table(table))) &&
+        cnf.where_expr.as_ref().map(|where_expr| where_expr.matches(q)).unwrap_or(true) &&
+        (cnf.regexes.len() == 0 || cnf.regexes.iter().any(|regex| regex.is_match(&q.query))) &&
+        !cnf.exclude_regexes.iter().any(|regex| regex.is_match(&q.query))
+}
+
+/// Whether query passes all filters including count range
+fn passes(q: &Query, cnf: &Config, totals: &HashMap<String, Totals>, timestamp_floor: i64) -> bool {
+    let count = totals.get(&q.query).map(|totals| totals.count).unwrap_or(1);
+
+    count >= cnf.count_min && count <= cnf.count_max && matches(q, cnf, timestamp_floor)
+}
+
+fn filter(qq: Vec<Query>, cnf: &Config, totals: &HashMap<String, Totals>, mapflt: &mut usize, timestamp_floor: i64) -> Vec<Query> {
+    qq.into_iter().filter(|q| {
+        let not_filtered = passes(q, cnf, totals, timestamp_floor);
+
         if !not_filtered {
             *mapflt += 1;
         }
 
         not_filtered
-    }).cloned().collect()
+    }).collect()
+}
+
+/// Whether candidate replaces the query kept so far for its fingerprint.
+/// Ties go to the later query.
+fn keeps(keep: &DedupKeep, kept: &Query, candidate: &Query) -> bool {
+    match keep {
+        &DedupKeep::Slowest => candidate.query_time >= kept.query_time,
+        &DedupKeep::Examined => candidate.rows_examined >= kept.rows_examined,
+        &DedupKeep::First => false,
+        &DedupKeep::Last => true
+    }
 }
 
-fn make_dedup_hash(qq: &Vec<Query>) -> HashMap<&String, &Query> {
-    let mut dedup_hash: HashMap<&String, &Query> = HashMap::new();
+/// Indices of one query kept per fingerprint, in log order, and totals of every group
+fn dedup(qq: &Vec<Query>, keep: &DedupKeep) -> (Vec<usize>, HashMap<String, Totals>) {
+    let mut kept: HashMap<&String, usize> = HashMap::new();
+    let mut groups: HashMap<String, Totals> = HashMap::new();
 
-    for q in qq.iter() {
-        if !dedup_hash.get(&q.query).is_none() {
-            dedup_hash.remove(&q.query);
-        }
+    for (index, q) in qq.iter().enumerate() {
+        groups.entry(q.fingerprint.clone()).or_insert_with(Totals::new).add(q);
+
+        let replace = kept.get(&q.fingerprint).map(|&kept_index| keeps(keep, &qq[kept_index], q)).unwrap_or(true);
 
-        dedup_hash.insert(&q.query, q);
+        if replace {
+            kept.insert(&q.fingerprint, index);
+        }
     }
 
-    dedup_hash
+    let mut listed: Vec<usize> = kept.values().cloned().collect();
+
+    listed.sort();
+
+    (listed, groups)
 }
 
-pub fn process(qq: &mut Vec<Query>, web: bool) {
+pub fn process(qq: Vec<Query>, cnf: &Config) -> Snapshot {
     let mut mapflt: usize = 0;
-    let mut queries_hash = qhash.lock().unwrap();
-    let wdelay = config.lock().unwrap().wpd;
-
-    queries_hash.clear();
+    let mut queries_hash: HashMap<String, Totals> = HashMap::new();
 
     for q in qq.iter() {
-        let count = queries_hash.entry(q.query.clone()).or_insert(0);
-        *count += 1;
-        sleep(wdelay);
+        queries_hash.entry(q.query.clone()).or_insert_with(Totals::new).add(q);
     }
 
+    let mut fingerprints_hash: HashMap<String, Totals> = HashMap::new();
+
+    if cnf.sort.iter().any(|key| if let SortField::ExaminedRatio = key.field { true } else { false }) {
+        for q in qq.iter() {
+            fingerprints_hash.entry(q.fingerprint.clone()).or_insert_with(Totals::new).add(q);
+        }
+    }
 
-    let mut new_qq = {
-        if  config.lock().unwrap().dedup {
-            let mut dedup_hash = make_dedup_hash(&qq);
-            let mut dedupd_qq: Vec<Query> = Vec::new();
+    let mut queries_sample: HashMap<String, String> = HashMap::new();
 
-            for (_, &q) in dedup_hash.iter() {
-                dedupd_qq.push(q.clone());
+    if let QueryView::Sample = cnf.query_view {
+        for q in qq.iter() {
+            if !queries_sample.contains_key(&q.fingerprint) {
+                queries_sample.insert(q.fingerprint.clone(), q.raw_query.clone());
             }
+        }
+    }
 
-            filter(&dedupd_qq, &mut mapflt)
+    let timestamp_floor = {
+        if cnf.last > 0 {
+            qq.iter().map(|q| q.timestamp).max().unwrap_or(0) - cnf.last
         } else {
-            filter(qq, &mut mapflt)
+            -1
         }
     };
 
-    sort(&mut new_qq);
-    let cnf = config.lock().unwrap();
-
-    {
-        match cnf.sort_type {
-            QueriesSortType::Count =>
-                new_qq.sort_by(|lhs, rhs|
-                    (*queries_hash.get(&lhs.query).unwrap())
-                        .partial_cmp(queries_hash.get(&rhs.query).unwrap()).unwrap()),
+    let total = qq.len();
+    let new_qq = filter(qq, cnf, &queries_hash, &mut mapflt, timestamp_floor);
 
-            QueriesSortType::CountInverse =>
-                new_qq.sort_by(|lhs, rhs|
-                    (*queries_hash.get(&rhs.query).unwrap())
-                        .partial_cmp(queries_hash.get(&lhs.query).unwrap()).unwrap()),
+    // Lint, baseline and gate see every query passing filters, as reports do
+    let (findings, profile, verdict) = {
+        let passed: Vec<&Query> = new_qq.iter().collect();
+        let mut findings = Findings::new();
 
-            _ => {}
+        if cnf.lint.len() > 0 {
+            for q in passed.iter() {
+                findings.add(q, &cnf.lint);
+            }
         }
+
+        let profile = if cnf.baseline.is_some() || cnf.save_baseline.len() > 0 {
+            Some(Baseline::build(&passed))
+        } else {
+            None
+        };
+
+        let verdict = if cnf.gate() {
+            Some(Verdict::new(&passed, cnf))
+        } else {
+            None
+        };
+
+        (findings, profile, verdict)
+    };
+
+    // Dedup after filters, so the kept query and group stats are of matching queries only.
+    // Only the listing is deduplicated, reports and --group_by see every query.
+    let (listed, groups) = match cnf.dedup {
+        Some(ref keep) => dedup(&new_qq, keep),
+        None => ((0..new_qq.len()).collect(), HashMap::new())
+    };
+    let duplicates = new_qq.len() - listed.len();
+    let order = rank(&new_qq, &listed, cnf, &queries_hash, &fingerprints_hash);
+
+    Snapshot {
+        queries: new_qq,
+        order,
+        totals: queries_hash,
+        samples: queries_sample,
+        groups,
+        findings,
+        profile,
+        verdict,
+        total,
+        filtered_out: mapflt,
+        duplicates
     }
+}
 
-    if web {
-        let mut web_queries = wqq.lock().unwrap();
+pub fn print(snapshot: &Snapshot, cnf: &Config) {
+    if cnf.json() {
+        println!("{}", json::snapshot(snapshot, cnf));
+        return;
+    }
 
-        web_queries.clear();
-        web_queries.append(&mut new_qq);
-    } else {
-        for (index, q) in new_qq.iter().enumerate() {
-            let count = queries_hash.get(&q.query).unwrap();
+    let new_qq = &snapshot.queries;
 
-            if *count >= cnf.count_min && *count <= cnf.count_max {
-                println!("{}", q.to_string(index + 1, *count));
-            }
+    for (index, q) in snapshot.shown().into_iter().enumerate() {
+        println!("{}", snapshot.render(index + 1, q, cnf));
+    }
 
-            if index == cnf.limit {
-                break;
-            }
-        }
+    for report in cnf.reports.iter() {
+        println!("{}", reports::render(report, new_qq, cnf));
+    }
 
-        println!("TOTAL: {}", qq.len());
+    if cnf.group_by.len() > 0 {
+        println!("{}", groups::report(new_qq, cnf));
+    }
 
-        let filtered = (if new_qq.len() < cnf.limit { 0 } else { qq.len() - new_qq.len() }) +
-            (if cnf.limit < new_qq.len() && (new_qq.len() - cnf.limit) > 0 { new_qq.len() - cnf.limit - 1 } else { 0 }) + mapflt;
+    if cnf.lint.len() > 0 {
+        println!("{}", snapshot.findings.to_string());
+    }
 
-        if filtered > 0 {
-            println!("FILTERED: {}", filtered.to_string());
+    if let Some(ref profile) = snapshot.profile {
+        if cnf.baseline.is_some() {
+            println!("{}", baseline::report(profile, cnf));
         }
     }
 
-    qq.clear();
-    new_qq.clear();
+    if let Some(ref verdict) = snapshot.verdict {
+        println!("{}", verdict.to_string());
+    }
+
+    println!("TOTAL: {}", snapshot.total);
+
+    if snapshot.filtered_out > 0 {
+        println!("FILTERED: {}", snapshot.filtered_out);
+    }
+
+    if snapshot.duplicates > 0 {
+        println!("DUPLICATES: {}", snapshot.duplicates);
+    }
+
+    println!("SHOWN: {}", snapshot.order.len());
 }
@@ -1,5 +1,21 @@
 use regex::{Captures, Regex};
 
+/// Service statements skipped by --ignore_noise: connection pings, session setup,
+/// transaction control and backup tools traffic
+pub const NOISE_PATTERNS: &'static [&'static str] = &[
+    r"^SELECT\s+@@",
+    r"^SELECT\s+(1|VERSION\(\)|DATABASE\(\)|CONNECTION_ID\(\))\s*;$",
+    r"^SHOW\s",
+    r"^SET\s",
+    r"^USE\s",
+    r"^(COMMIT|ROLLBACK|BEGIN|START TRANSACTION)\b",
+    r"^FLUSH\s",
+    r"^UNLOCK TABLES",
+    r"SQL_NO_CACHE",
+    r"^/\*!40\d{3}",
+    r"^(administrator command|Quit|Ping)\b"
+];
+
 pub fn is_info(line: &String) -> bool {
     lazy_static! {
         static ref regex: Regex = Regex::new(r"^# .*$").unwrap();
@@ -24,6 +40,25 @@ pub fn db(line: &String) -> Option<Captures> {
     regex.captures(line)
 }
 
+/// All "Name: value" pairs of a header line
+pub fn info_fields(line: &String) -> Vec<(String, String)> {
+    lazy_static! {
+        static ref regex: Regex = Regex::new(r"(?P<name>[A-Za-z][A-Za-z_]*):\s+(?P<value>[^\s]+)").unwrap();
+    }
+
+    regex.captures_iter(line)
+        .map(|capts| (capts["name"].to_string(), capts["value"].to_string()))
+        .collect()
+}
+
+pub fn user_host(line: &String) -> Option<Captures> {
+    lazy_static! {
+        static ref regex: Regex = Regex::new(r"^# User@Host: (?P<user>[^\[]*)\[[^\]]*\]\s*@\s*(?P<host>[^\[\s]*)\s*\[(?P<ip>[^\]]*)\](?:\s+Id:\s*(?P<id>\d+))?.*$").unwrap();
+    }
+
+    regex.captures(line)
+}
+
 pub fn date_time(line: &String) -> Option<Captures> {
     lazy_static! {
         static ref regex: Regex = Regex::new(r"^# .*Time: (?P<year>\d{2})(?P<month>\d{2})(?P<day>\d{2})[^\d]+(?P<hour>\d+).(?P<minute>\d{2}).(?P<second>\d{2}).*$").unwrap();
@@ -93,7 +128,7 @@ pub fn is_query_end(line: &String) -> bool {
         static ref regex: Regex = Regex::new(r"^.*;$").unwrap();
     }
 
-    !regex.find(line).is_none()
+    regex.is_match(line)
 }
 
 pub fn abs_numbers(line: &String) -> String {
@@ -134,7 +169,7 @@ pub fn prs_spaces_trim(line: &String) -> String {
 
 pub fn remove_comments(line: &String) -> String {
     lazy_static! {
-        static ref aster_regex: Regex = Regex::new(r"/\*[^!].+?\*/").unwrap();
+        static ref aster_regex: Regex = Regex::new(r"/\*(?:[^!][\s\S]*?)?\*/").unwrap();
     }
 
     lazy_static! {
@@ -150,3 +185,45 @@ pub fn remove_comments(line: &String) -> String {
 
     hash_regex.replace_all(&dash_ret, "").into()
 }
+
+/// Bodies of /* */ comments, except /*! */ executable ones, may span lines
+pub fn comments(query: &String) -> Vec<String> {
+    lazy_static! {
+        static ref regex: Regex = Regex::new(r"/\*(?P<body>(?:[^!][\s\S]*?)?)\*/").unwrap();
+    }
+
+    regex.captures_iter(query).map(|capts| capts["body"].to_string()).collect()
+}
+
+pub fn comment_tags(comment: &String) -> Vec<(String, String)> {
+    lazy_static! {
+        static ref regex: Regex = Regex::new(r#"(?:^|[\s,])(?P<key>[A-Za-z_][\w.\-]*)[:=](?P<value>'[^']*'|"[^"]*"|[^\s,]+)"#).unwrap();
+    }
+
+    regex.captures_iter(comment).map(|capts| {
+        let value = capts["value"].trim_matches(|c| c == '\'' || c == '"').to_string();
+
+        (capts["key"].to_string(), url_decode(&value))
+    }).collect()
+}
+
+fn url_decode(value: &String) -> String {
+    let bytes = value.as_bytes();
+    let mut ret: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] == b'%' && index + 2 < bytes.len() &&
+            (bytes[index + 1] as char).is_digit(16) && (bytes[index + 2] as char).is_digit(16) {
+            let hex = String::from_utf8_lossy(&bytes[index + 1..index + 3]).into_owned();
+
+            ret.push(u8::from_str_radix(&hex, 16).unwrap());
+            index += 3;
+        } else {
+            ret.push(bytes[index]);
+            index += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&ret).into_owned()
+}
@@ -0,0 +1,219 @@
+use types::{Query, Config, ReportType};
+use stats::{self, Histogram};
+use concurrency;
+use locks;
+use sessions;
+use bursts;
+use std::collections::HashMap;
+
+struct TimeStat {
+    count: usize,
+    total_time: f64
+}
+
+impl TimeStat {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            total_time: 0.0
+        }
+    }
+
+    fn add(&mut self, q: &Query) {
+        self.count += 1;
+
+        if q.query_time > 0.0 {
+            self.total_time += q.query_time;
+        }
+    }
+}
+
+/// Worst offenders shown in summary report
+const WORST_OFFENDERS: usize = 10;
+
+/// Rows examined and sent by queries with one fingerprint
+struct Efficiency {
+    count: usize,
+    rows_examined: i64,
+    rows_sent: i64,
+    full_scans: usize,
+    total_time: f64
+}
+
+impl Efficiency {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            rows_examined: 0,
+            rows_sent: 0,
+            full_scans: 0,
+            total_time: 0.0
+        }
+    }
+
+    fn add(&mut self, q: &Query) {
+        self.count += 1;
+
+        if q.rows_examined >= 0 {
+            self.rows_examined += q.rows_examined;
+        }
+
+        if q.rows_sent >= 0 {
+            self.rows_sent += q.rows_sent;
+        }
+
+        if q.extended_value("full_scan") == Some(1.0) {
+            self.full_scans += 1;
+        }
+
+        if q.query_time > 0.0 {
+            self.total_time += q.query_time;
+        }
+    }
+
+    fn ratio(&self) -> f64 {
+        stats::examined_ratio(self.rows_examined, self.rows_sent)
+    }
+}
+
+/// Fingerprints likely missing an index, by rows examined per row sent
+fn worst_offenders(efficiency: HashMap<&String, Efficiency>) -> String {
+    let mut rows: Vec<(&String, Efficiency)> = efficiency.into_iter()
+        .filter(|&(_, ref eff)| stats::missing_index(eff.count, eff.rows_examined, eff.rows_sent, eff.full_scans))
+        .collect();
+
+    rows.sort_by(|lhs, rhs| rhs.1.ratio().partial_cmp(&lhs.1.ratio()).unwrap()
+        .then_with(|| rhs.1.total_time.partial_cmp(&lhs.1.total_time).unwrap())
+        .then_with(|| lhs.0.cmp(rhs.0)));
+
+    let mut buf = "Worst offenders (likely missing index):\n".to_string();
+
+    if rows.len() == 0 {
+        buf.push_str("\tNone\n");
+        return buf;
+    }
+
+    buf.push_str(&format!("\t{:>18} {:>14} {:>10} {:>14} {}\n",
+                          "EXAMINED_PER_SENT", "AVG_EXAMINED", "FULL_SCANS", "TOTAL_TIME", "FINGERPRINT"));
+
+    for &(fingerprint, ref eff) in rows.iter().take(WORST_OFFENDERS) {
+        buf.push_str(&format!("\t{:>18.1} {:>14.1} {:>10} {:>14.6} {}\n",
+                              eff.ratio(), eff.rows_examined as f64 / eff.count as f64,
+                              eff.full_scans, eff.total_time, fingerprint));
+    }
+
+    buf
+}
+
+fn time_stat_table(title: &str, stats: HashMap<String, TimeStat>) -> String {
+    let mut rows: Vec<(String, TimeStat)> = stats.into_iter().collect();
+
+    rows.sort_by(|lhs, rhs| rhs.1.total_time.partial_cmp(&lhs.1.total_time).unwrap()
+        .then_with(|| lhs.0.cmp(&rhs.0)));
+
+    let mut buf = format!("{:<48} {:>10} {:>14} {:>12}\n", title, "COUNT", "TOTAL_TIME", "AVG_TIME");
+
+    for (name, stat) in rows.iter() {
+        buf.push_str(&format!("{:<48} {:>10} {:>14.6} {:>12.6}\n",
+                              name, stat.count, stat.total_time, stat.total_time / stat.count as f64));
+    }
+
+    buf
+}
+
+fn tables(qq: &Vec<Query>) -> String {
+    let mut table_stats: HashMap<String, TimeStat> = HashMap::new();
+    let mut statement_stats: HashMap<String, TimeStat> = HashMap::new();
+
+    for q in qq.iter() {
+        for table in q.tables.iter() {
+            table_stats.entry(table.clone()).or_insert_with(TimeStat::new).add(q);
+        }
+
+        statement_stats.entry(q.statement.clone()).or_insert_with(TimeStat::new).add(q);
+    }
+
+    format!("TABLES REPORT:\n{}\n{}", time_stat_table("TABLE", table_stats),
+            time_stat_table("STATEMENT", statement_stats))
+}
+
+fn summary(qq: &Vec<Query>, cnf: &Config) -> String {
+    let mut ts_min = ::std::i64::MAX;
+    let mut ts_max = ::std::i64::MIN;
+    let mut total_time = 0.0;
+    let mut total_lock_time = 0.0;
+    let mut total_rows_examined: i64 = 0;
+    let mut efficiency: HashMap<&String, Efficiency> = HashMap::new();
+    let mut query_time_hist = Histogram::time();
+    let mut lock_time_hist = Histogram::time();
+    let mut rows_examined_hist = Histogram::rows();
+    let mut query_times: Vec<f64> = Vec::with_capacity(qq.len());
+
+    for q in qq.iter() {
+        if q.timestamp >= 0 {
+            ts_min = ts_min.min(q.timestamp);
+            ts_max = ts_max.max(q.timestamp);
+        }
+
+        if q.query_time >= 0.0 {
+            total_time += q.query_time;
+            query_times.push(q.query_time);
+        }
+
+        if q.lock_time >= 0.0 {
+            total_lock_time += q.lock_time;
+        }
+
+        if q.rows_examined >= 0 {
+            total_rows_examined += q.rows_examined;
+        }
+
+        efficiency.entry(&q.fingerprint).or_insert_with(Efficiency::new).add(q);
+        query_time_hist.add(q.query_time);
+        lock_time_hist.add(q.lock_time);
+        rows_examined_hist.add(q.rows_examined as f64);
+    }
+
+    let mut buf = "SUMMARY REPORT:\n".to_string();
+
+    if ts_min <= ts_max {
+        let span = ts_max - ts_min;
+
+        buf.push_str(&format!("\tTime range: {} - {} ({}s)\n",
+                              cnf.format_timestamp(ts_min),
+                              cnf.format_timestamp(ts_max),
+                              span));
+        buf.push_str(&format!("\tQueries per second: {:.4}\n",
+                              qq.len() as f64 / (if span > 0 { span } else { 1 }) as f64));
+    } else {
+        buf.push_str("\tTime range: ?\n");
+    }
+
+    buf.push_str(&format!("\tQueries: {}\n", qq.len()));
+    buf.push_str(&format!("\tDistinct fingerprints: {}\n", efficiency.len()));
+    buf.push_str(&format!("\tTotal query time: {:.6}\n", total_time));
+    buf.push_str(&format!("\tQuery time p50/p95/p99: {:.6} / {:.6} / {:.6}\n",
+                          stats::percentile(&mut query_times, 50.0),
+                          stats::percentile(&mut query_times, 95.0),
+                          stats::percentile(&mut query_times, 99.0)));
+    buf.push_str(&format!("\tTotal lock time: {:.6}\n", total_lock_time));
+    buf.push_str(&format!("\tTotal rows examined: {}\n\n", total_rows_examined));
+    buf.push_str(&query_time_hist.to_string("Query_time"));
+    buf.push_str(&lock_time_hist.to_string("Lock_time"));
+    buf.push_str(&rows_examined_hist.to_string("Rows_examined"));
+    buf.push_str(&worst_offenders(efficiency));
+
+    buf
+}
+
+pub fn render(report: &ReportType, qq: &Vec<Query>, cnf: &Config) -> String {
+    match report {
+        &ReportType::Tables => tables(qq),
+        &ReportType::Summary => summary(qq, cnf),
+        &ReportType::Concurrency => concurrency::report(qq, cnf),
+        &ReportType::Locks => locks::report(qq, cnf),
+        &ReportType::Sessions => sessions::report(qq, cnf),
+        &ReportType::NPlusOne => bursts::report(qq, cnf),
+        &ReportType::Undefined => String::new()
+    }
+}
@@ -0,0 +1,170 @@
+use types::{Query, Config};
+use std::collections::BTreeMap;
+
+/// Connections shown, by total query time
+const CONNECTIONS_SHOWN: usize = 20;
+
+/// Transactions shown, by duration
+const TRANSACTIONS_SHOWN: usize = 10;
+
+/// Statements shown per transaction
+const STATEMENTS_SHOWN: usize = 20;
+
+/// Statements of one connection between BEGIN and COMMIT or ROLLBACK
+struct Transaction<'a> {
+    connection_id: i64,
+    statements: Vec<&'a Query>,
+    end: &'static str
+}
+
+impl<'a> Transaction<'a> {
+    fn new(connection_id: i64) -> Self {
+        Self {
+            connection_id,
+            statements: Vec::new(),
+            end: "OPEN"
+        }
+    }
+
+    fn started(&self) -> f64 {
+        self.statements.iter().map(|q| q.start()).fold(::std::f64::MAX, f64::min)
+    }
+
+    fn ended(&self) -> f64 {
+        self.statements.iter().map(|q| q.timestamp as f64).fold(::std::f64::MIN, f64::max)
+    }
+
+    fn duration(&self) -> f64 {
+        self.ended() - self.started()
+    }
+}
+
+fn begins(q: &Query) -> bool {
+    q.statement == "BEGIN" || q.statement == "START TRANSACTION"
+}
+
+/// ROLLBACK TO SAVEPOINT keeps the transaction open
+fn ends(q: &Query) -> Option<&'static str> {
+    match &*q.statement {
+        "COMMIT" => Some("COMMIT"),
+        "ROLLBACK" if !q.query.to_uppercase().starts_with("ROLLBACK TO") => Some("ROLLBACK"),
+        _ => None
+    }
+}
+
+/// Statements of every connection in the order they ended
+fn by_connection(qq: &Vec<Query>) -> BTreeMap<i64, Vec<&Query>> {
+    let mut connections: BTreeMap<i64, Vec<&Query>> = BTreeMap::new();
+
+    for q in qq.iter().filter(|q| q.connection_id >= 0 && q.timestamp >= 0) {
+        connections.entry(q.connection_id).or_insert_with(Vec::new).push(q);
+    }
+
+    for statements in connections.values_mut() {
+        statements.sort_by_key(|q| q.timestamp);
+    }
+
+    connections
+}
+
+/// BEGIN while a transaction is open commits it implicitly, as the server does
+fn split_transactions<'a>(connection_id: i64, statements: &Vec<&'a Query>) -> Vec<Transaction<'a>> {
+    let mut transactions: Vec<Transaction> = Vec::new();
+    let mut current: Option<Transaction> = None;
+
+    for &q in statements.iter() {
+        if begins(q) {
+            if let Some(mut transaction) = current.take() {
+                transaction.end = "IMPLICIT COMMIT";
+                transactions.push(transaction);
+            }
+
+            let mut transaction = Transaction::new(connection_id);
+
+            transaction.statements.push(q);
+            current = Some(transaction);
+        } else if let Some(mut transaction) = current.take() {
+            transaction.statements.push(q);
+
+            match ends(q) {
+                Some(end) => {
+                    transaction.end = end;
+                    transactions.push(transaction);
+                }
+                None => current = Some(transaction)
+            }
+        }
+    }
+
+    if let Some(transaction) = current {
+        transactions.push(transaction);
+    }
+
+    transactions
+}
+
+pub fn report(qq: &Vec<Query>, cnf: &Config) -> String {
+    let connections = by_connection(qq);
+    let mut transactions: Vec<Transaction> = Vec::new();
+
+    for (&connection_id, statements) in connections.iter() {
+        transactions.extend(split_transactions(connection_id, statements));
+    }
+
+    let mut buf = format!("SESSIONS REPORT:\n\tConnections: {}\n\tTransactions: {}\n\n",
+                          connections.len(), transactions.len());
+
+    let mut rows: Vec<(&i64, &Vec<&Query>, f64)> = connections.iter()
+        .map(|(connection_id, statements)|
+            (connection_id, statements, statements.iter().map(|q| q.query_time.max(0.0)).sum()))
+        .collect();
+
+    rows.sort_by(|lhs, rhs| rhs.2.partial_cmp(&lhs.2).unwrap().then_with(|| lhs.0.cmp(rhs.0)));
+
+    buf.push_str(&format!("{:>12} {:<24} {:<24} {:>10} {:>14}\n", "CONNECTION", "USER@HOST", "FIRST", "STATEMENTS", "QUERY_TIME"));
+
+    for &(connection_id, statements, query_time) in rows.iter().take(CONNECTIONS_SHOWN) {
+        let first = statements[0];
+
+        buf.push_str(&format!("{:>12} {:<24} {:<24} {:>10} {:>14.6}\n",
+                              connection_id, format!("{}@{}", first.user, first.host),
+                              cnf.format_timestamp(first.timestamp), statements.len(), query_time));
+    }
+
+    transactions.sort_by(|lhs, rhs| rhs.duration().partial_cmp(&lhs.duration()).unwrap()
+        .then_with(|| lhs.started().partial_cmp(&rhs.started()).unwrap())
+        .then_with(|| lhs.connection_id.cmp(&rhs.connection_id)));
+
+    buf.push_str("\nLongest transactions:\n");
+
+    if transactions.len() == 0 {
+        buf.push_str("\tNone\n");
+        return buf;
+    }
+
+    buf.push_str(&format!("\t{:>12} {:<24} {:>10} {:>10} {:>14} {:>14} {}\n",
+                          "CONNECTION", "BEGIN", "DURATION", "STATEMENTS", "QUERY_TIME", "LOCK_TIME", "END"));
+
+    for transaction in transactions.iter().take(TRANSACTIONS_SHOWN) {
+        let started = transaction.started();
+
+        buf.push_str(&format!("\t{:>12} {:<24} {:>10.3} {:>10} {:>14.6} {:>14.6} {}\n",
+                              transaction.connection_id,
+                              cnf.format_timestamp(started.floor() as i64),
+                              transaction.duration(),
+                              transaction.statements.len(),
+                              transaction.statements.iter().map(|q| q.query_time.max(0.0)).sum::<f64>(),
+                              transaction.statements.iter().map(|q| q.lock_time.max(0.0)).sum::<f64>(),
+                              transaction.end));
+
+        for q in transaction.statements.iter().take(STATEMENTS_SHOWN) {
+            buf.push_str(&format!("\t\t+{:<10.3} {:>10.6} {}\n", q.start() - started, q.query_time.max(0.0), q.fingerprint));
+        }
+
+        if transaction.statements.len() > STATEMENTS_SHOWN {
+            buf.push_str(&format!("\t\t... {} more\n", transaction.statements.len() - STATEMENTS_SHOWN));
+        }
+    }
+
+    buf
+}
@@ -0,0 +1,292 @@
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Word(String),
+    Quoted(String),
+    Str(String),
+    Number(String),
+    Punct(String)
+}
+
+impl Token {
+    pub fn is_word(&self, word: &str) -> bool {
+        match self {
+            &Token::Word(ref value) => value.eq_ignore_ascii_case(word),
+            _ => false
+        }
+    }
+
+    pub fn is_punct(&self, punct: &str) -> bool {
+        match self {
+            &Token::Punct(ref value) => value == punct,
+            _ => false
+        }
+    }
+}
+
+const RESERVED: &'static [&'static str] = &[
+    "WHERE", "JOIN", "INNER", "LEFT", "RIGHT", "OUTER", "CROSS", "NATURAL", "STRAIGHT_JOIN",
+    "ON", "USING", "GROUP", "ORDER", "LIMIT", "HAVING", "SET", "VALUES", "VALUE", "SELECT",
+    "UNION", "FOR", "LOCK", "USE", "FORCE", "IGNORE", "PARTITION", "WINDOW", "INTO",
+    "PROCEDURE", "FROM", "DUPLICATE"
+];
+
+const TABLE_MODIFIERS: &'static [&'static str] = &[
+    "LOW_PRIORITY", "HIGH_PRIORITY", "DELAYED", "IGNORE", "QUICK", "ONLY", "LATERAL"
+];
+
+pub fn tokenize(query: &str) -> Vec<Token> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+
+        if c.is_whitespace() {
+            index += 1;
+        } else if c == '\'' || c == '"' || c == '`' {
+            let start = index + 1;
+            index += 1;
+
+            while index < chars.len() {
+                if chars[index] == '\\' {
+                    index += 2;
+                    continue;
+                }
+
+                if chars[index] == c {
+                    if index + 1 < chars.len() && chars[index + 1] == c {
+                        index += 2;
+                        continue;
+                    }
+
+                    break;
+                }
+
+                index += 1;
+            }
+
+            let end = if index < chars.len() { index } else { chars.len() };
+            let value: String = chars[start.min(end)..end].iter().collect();
+
+            tokens.push(if c == '`' { Token::Quoted(value) } else { Token::Str(value) });
+            index += 1;
+        } else if c.is_digit(10) {
+            let start = index;
+
+            while index < chars.len() && (chars[index].is_alphanumeric() || chars[index] == '.') {
+                index += 1;
+            }
+
+            tokens.push(Token::Number(chars[start..index].iter().collect()));
+        } else if c.is_alphanumeric() || c == '_' || c == '$' || c == '@' {
+            let start = index;
+
+            while index < chars.len() &&
+                (chars[index].is_alphanumeric() || chars[index] == '_' || chars[index] == '$' || chars[index] == '@') {
+                index += 1;
+            }
+
+            tokens.push(Token::Word(chars[start..index].iter().collect()));
+        } else {
+            let pair: String = chars[index..(index + 2).min(chars.len())].iter().collect();
+
+            if pair == "<=" || pair == ">=" || pair == "<>" || pair == "!=" || pair == ":=" {
+                tokens.push(Token::Punct(pair));
+                index += 2;
+            } else {
+                tokens.push(Token::Punct(c.to_string()));
+                index += 1;
+            }
+        }
+    }
+
+    tokens
+}
+
+pub fn is_reserved(token: &Token) -> bool {
+    RESERVED.iter().any(|word| token.is_word(word))
+}
+
+pub fn skip_parens(tokens: &[Token], mut index: usize) -> usize {
+    let mut depth = 0;
+
+    while index < tokens.len() {
+        if tokens[index].is_punct("(") {
+            depth += 1;
+        } else if tokens[index].is_punct(")") {
+            depth -= 1;
+
+            if depth == 0 {
+                return index + 1;
+            }
+        }
+
+        index += 1;
+    }
+
+    index
+}
+
+fn table_name(tokens: &[Token], index: usize) -> Option<(String, usize)> {
+    let name_part = |token: &Token| -> Option<String> {
+        match token {
+            &Token::Quoted(ref value) => Some(value.clone()),
+            &Token::Word(ref value) if !is_reserved(token) && !value.starts_with('@') => Some(value.clone()),
+            _ => None
+        }
+    };
+
+    let mut name = match tokens.get(index).and_then(|token| name_part(token)) {
+        Some(name) => name,
+        None => return None
+    };
+
+    let mut next = index + 1;
+
+    if next + 1 < tokens.len() && tokens[next].is_punct(".") {
+        if let Some(part) = name_part(&tokens[next + 1]) {
+            name = format!("{}.{}", name, part);
+            next += 2;
+        }
+    }
+
+    Some((name.to_lowercase(), next))
+}
+
+/// Reads comma separated table references after FROM, JOIN, UPDATE and INTO keywords.
+/// Subqueries are skipped here: their own FROM clauses are visited by the caller.
+fn table_refs(tokens: &[Token], mut index: usize, list: bool, tables: &mut Vec<String>) {
+    loop {
+        while index < tokens.len() && TABLE_MODIFIERS.iter().any(|word| tokens[index].is_word(word)) {
+            index += 1;
+        }
+
+        let (name, next) = match table_name(tokens, index) {
+            Some(name) => name,
+            None => return
+        };
+
+        if !tables.contains(&name) {
+            tables.push(name);
+        }
+
+        index = next;
+
+        if !list {
+            return;
+        }
+
+        if index < tokens.len() && tokens[index].is_word("AS") {
+            index += 1;
+        }
+
+        if index < tokens.len() && !is_reserved(&tokens[index]) {
+            match tokens[index] {
+                Token::Word(_) | Token::Quoted(_) => index += 1,
+                _ => {}
+            }
+        }
+
+        while index + 1 < tokens.len() &&
+            (tokens[index].is_word("USE") || tokens[index].is_word("FORCE") || tokens[index].is_word("IGNORE")) &&
+            (tokens[index + 1].is_word("INDEX") || tokens[index + 1].is_word("KEY")) {
+            while index < tokens.len() && !tokens[index].is_punct("(") {
+                index += 1;
+            }
+
+            index = skip_parens(tokens, index);
+        }
+
+        if index < tokens.len() && tokens[index].is_punct(",") {
+            index += 1;
+        } else {
+            return;
+        }
+    }
+}
+
+pub fn tables(tokens: &[Token]) -> Vec<String> {
+    let mut tables: Vec<String> = Vec::new();
+    let mut scopes: Vec<bool> = vec![true];
+
+    for (index, token) in tokens.iter().enumerate() {
+        if token.is_punct("(") {
+            let subquery = tokens.get(index + 1).map(|next| next.is_word("SELECT")).unwrap_or(false);
+            scopes.push(subquery);
+            continue;
+        }
+
+        if token.is_punct(")") {
+            if scopes.len() > 1 {
+                scopes.pop();
+            }
+
+            continue;
+        }
+
+        if !*scopes.last().unwrap() {
+            continue;
+        }
+
+        let prev_word = |word: &str| index > 0 && tokens[index - 1].is_word(word);
+
+        if token.is_word("FROM") {
+            table_refs(tokens, index + 1, true, &mut tables);
+        } else if token.is_word("JOIN") {
+            table_refs(tokens, index + 1, false, &mut tables);
+        } else if token.is_word("UPDATE") && !prev_word("KEY") && !prev_word("FOR") {
+            table_refs(tokens, index + 1, true, &mut tables);
+        } else if token.is_word("INTO") {
+            let outfile = tokens.get(index + 1)
+                .map(|next| next.is_word("OUTFILE") || next.is_word("DUMPFILE"))
+                .unwrap_or(false);
+
+            if !outfile {
+                table_refs(tokens, index + 1, false, &mut tables);
+            }
+        }
+    }
+
+    tables
+}
+
+pub fn statement_type(tokens: &[Token]) -> String {
+    let first = tokens.iter().position(|token| !token.is_punct("("));
+
+    let first = match first {
+        Some(first) => first,
+        None => return "?".to_string()
+    };
+
+    if tokens[first].is_word("WITH") {
+        let mut depth = 0;
+
+        for token in tokens[first..].iter() {
+            if token.is_punct("(") {
+                depth += 1;
+            } else if token.is_punct(")") {
+                depth -= 1;
+            } else if depth == 0 {
+                for stmt in ["SELECT", "INSERT", "UPDATE", "DELETE", "REPLACE"].iter() {
+                    if token.is_word(stmt) {
+                        return stmt.to_string();
+                    }
+                }
+            }
+        }
+    }
+
+    match tokens[first] {
+        Token::Word(ref word) => {
+            let word = word.to_uppercase();
+
+            if word == "START" && tokens.get(first + 1).map(|next| next.is_word("TRANSACTION")).unwrap_or(false) {
+                "START TRANSACTION".to_string()
+            } else {
+                word
+            }
+        }
+        _ => "?".to_string()
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Write, Seek, SeekFrom, BufRead, BufReader};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+const HEAD_SIZE: u64 = 4096;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+const FNV_PRIME: u64 = 0x100_0000_01b3;
+
+/// Position of the previous run in the log, used to parse only appended entries
+pub struct State {
+    pub dev: u64,
+    pub ino: u64,
+    pub head_len: u64,
+    pub head_hash: u64,
+    pub offset: u64,
+    pub db: String,
+    pub timestamp: i64
+}
+
+/// FNV-1a of the log head. It is stored in the state file, so it must not
+/// depend on the toolchain like std hashers do
+fn head_hash(file: &mut File, len: u64) -> io::Result<u64> {
+    let mut head: Vec<u8> = Vec::new();
+
+    file.seek(SeekFrom::Start(0))?;
+    (&mut *file).take(len).read_to_end(&mut head)?;
+
+    Ok(head.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)))
+}
+
+/// Device and inode of the log
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> (u64, u64) {
+    (meta.dev(), meta.ino())
+}
+
+/// Elsewhere rotation is detected by size and head only
+#[cfg(not(unix))]
+fn file_id(_meta: &Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+/// Writes contents to a temporary file renamed over path, so a crash
+/// never leaves path truncated
+pub fn write_atomic(path: &String, contents: &str) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = File::create(&tmp_path)?;
+
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+impl State {
+    pub fn new(file: &mut File) -> io::Result<Self> {
+        let meta = file.metadata()?;
+        let head_len = meta.len().min(HEAD_SIZE);
+        let (dev, ino) = file_id(&meta);
+
+        Ok(Self {
+            dev,
+            ino,
+            head_len,
+            head_hash: head_hash(file, head_len)?,
+            offset: 0,
+            db: "?".to_string(),
+            timestamp: -1
+        })
+    }
+
+    /// Offset to continue from, 0 if the log was rotated or truncated since the previous run
+    pub fn resume_offset(&self, file: &mut File) -> io::Result<u64> {
+        let meta = file.metadata()?;
+
+        if file_id(&meta) != (self.dev, self.ino) || meta.len() < self.offset ||
+            meta.len() < self.head_len || head_hash(file, self.head_len)? != self.head_hash {
+            return Ok(0);
+        }
+
+        Ok(self.offset)
+    }
+
+    pub fn load(path: &String) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut state = Self {
+            dev: 0,
+            ino: 0,
+            head_len: 0,
+            head_hash: 0,
+            offset: 0,
+            db: "?".to_string(),
+            timestamp: -1
+        };
+
+        for line in reader.lines() {
+            let line = line?;
+            let mut line_split = line.splitn(2, '=');
+            let key = line_split.next().unwrap_or("");
+            let value = line_split.next().unwrap_or("");
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid state line: {}", line));
+
+            match key {
+                "dev" => state.dev = value.parse().map_err(|_| invalid())?,
+                "ino" => state.ino = value.parse().map_err(|_| invalid())?,
+                "head_len" => state.head_len = value.parse().map_err(|_| invalid())?,
+                "head_hash" => state.head_hash = value.parse().map_err(|_| invalid())?,
+                "offset" => state.offset = value.parse().map_err(|_| invalid())?,
+                "db" => state.db = value.to_string(),
+                "timestamp" => state.timestamp = value.parse().map_err(|_| invalid())?,
+                _ => {}
+            }
+        }
+
+        Ok(state)
+    }
+
+    pub fn save(&self, path: &String) -> io::Result<()> {
+        write_atomic(path, &format!("dev={}\nino={}\nhead_len={}\nhead_hash={}\noffset={}\ndb={}\ntimestamp={}\n",
+                                    self.dev, self.ino, self.head_len, self.head_hash, self.offset, self.db, self.timestamp))
+    }
+}
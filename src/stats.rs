@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+
+pub fn percentile(values: &mut Vec<f64>, p: f64) -> f64 {
+    if values.len() == 0 {
+        return 0.0;
+    }
+
+    values.sort_by(|lhs, rhs| lhs.partial_cmp(rhs).unwrap());
+
+    let rank = (p / 100.0 * values.len() as f64).ceil() as usize;
+
+    values[if rank > 0 { rank - 1 } else { 0 }.min(values.len() - 1)]
+}
+
+/// Rows examined per row sent from which a query likely misses an index
+const MISSING_INDEX_RATIO: f64 = 100.0;
+
+/// Average rows examined below which scans are too cheap to report
+const MISSING_INDEX_EXAMINED: f64 = 1000.0;
+
+/// Rows examined per row sent. Queries sending no rows count as sending one.
+pub fn examined_ratio(rows_examined: i64, rows_sent: i64) -> f64 {
+    rows_examined.max(0) as f64 / rows_sent.max(1) as f64
+}
+
+/// Queries examining many rows per row sent, or doing full scans, on average
+/// examining enough rows for it to matter
+pub fn missing_index(count: usize, rows_examined: i64, rows_sent: i64, full_scans: usize) -> bool {
+    rows_examined as f64 / count.max(1) as f64 >= MISSING_INDEX_EXAMINED &&
+        (examined_ratio(rows_examined, rows_sent) >= MISSING_INDEX_RATIO || full_scans > 0)
+}
+
+pub struct Histogram {
+    labels: Vec<&'static str>,
+    counts: Vec<usize>,
+    unit: f64,
+    zero_bucket: bool
+}
+
+impl Histogram {
+    /// pt-query-digest style buckets, from 1us to 10s+
+    pub fn time() -> Self {
+        Self {
+            labels: vec!["1us", "10us", "100us", "1ms", "10ms", "100ms", "1s", "10s+"],
+            counts: vec![0; 8],
+            unit: 0.000_001,
+            zero_bucket: false
+        }
+    }
+
+    pub fn rows() -> Self {
+        Self {
+            labels: vec!["0", "1", "10", "100", "1k", "10k", "100k", "1M+"],
+            counts: vec![0; 8],
+            unit: 1.0,
+            zero_bucket: true
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        if value < 0.0 {
+            return;
+        }
+
+        let last = self.counts.len() - 1;
+
+        let index = if value < self.unit {
+            0
+        } else {
+            (value / self.unit).log10().floor() as usize + if self.zero_bucket { 1 } else { 0 }
+        };
+
+        self.counts[index.min(last)] += 1;
+    }
+
+    pub fn to_string(&self, title: &str) -> String {
+        let max_count = self.counts.iter().cloned().max().unwrap_or(0);
+        let mut buf = format!("{} distribution:\n", title);
+
+        for (label, count) in self.labels.iter().zip(self.counts.iter()) {
+            let bar_len = if max_count > 0 {
+                ((*count as f64 / max_count as f64) * 60.0).ceil() as usize
+            } else {
+                0
+            };
+
+            buf.push_str(format!("\t{:>6} {:>10} {}", label, count, "#".repeat(bar_len)).trim_end());
+            buf.push_str("\n");
+        }
+
+        buf
+    }
+}
+
+/// Relative error of Sketch quantiles
+const SKETCH_ACCURACY: f64 = 0.01;
+
+/// Streaming quantile sketch with logarithmic buckets. Quantiles are within
+/// SKETCH_ACCURACY of the real value and memory depends only on the range
+/// of values, not on their number.
+#[derive(Clone)]
+pub struct Sketch {
+    gamma: f64,
+    buckets: BTreeMap<i32, u64>,
+    zeros: u64,
+    count: u64
+}
+
+impl Sketch {
+    pub fn new() -> Self {
+        Self {
+            gamma: (1.0 + SKETCH_ACCURACY) / (1.0 - SKETCH_ACCURACY),
+            buckets: BTreeMap::new(),
+            zeros: 0,
+            count: 0
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        if value < 0.0 {
+            return;
+        }
+
+        self.count += 1;
+
+        if value < 0.000_000_001 {
+            self.zeros += 1;
+        } else {
+            *self.buckets.entry((value.ln() / self.gamma.ln()).ceil() as i32).or_insert(0) += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Sketch) {
+        for (key, count) in other.buckets.iter() {
+            *self.buckets.entry(*key).or_insert(0) += *count;
+        }
+
+        self.zeros += other.zeros;
+        self.count += other.count;
+    }
+
+    /// Nearest rank percentile, as percentile() does for exact values
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+
+        let rank = ((p / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = self.zeros;
+
+        if seen >= rank {
+            return 0.0;
+        }
+
+        for (key, count) in self.buckets.iter() {
+            seen += *count;
+
+            if seen >= rank {
+                return 2.0 * self.gamma.powi(*key) / (self.gamma + 1.0);
+            }
+        }
+
+        0.0
+    }
+}
+
+/// Uniform random sample of at most capacity items from a stream of any length.
+/// Random numbers are seeded, so the sample is the same for the same input.
+#[derive(Clone)]
+pub struct Reservoir<T> {
+    capacity: usize,
+    seen: u64,
+    items: Vec<T>,
+    state: u64
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: 0,
+            items: Vec::new(),
+            state: 0x9E37_79B9_7F4A_7C15
+        }
+    }
+
+    /// xorshift64*
+    fn random(&mut self, bound: u64) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) % bound
+    }
+
+    pub fn add(&mut self, item: T) {
+        self.seen += 1;
+
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            let seen = self.seen;
+            let index = self.random(seen) as usize;
+
+            if index < self.capacity {
+                self.items[index] = item;
+            }
+        }
+    }
+
+    /// Sample of both streams, items are taken from each one in proportion to its length
+    pub fn merge(&mut self, other: Reservoir<T>) {
+        let mut lhs: Vec<T> = self.items.drain(..).collect();
+        let mut rhs = other.items;
+        let (mut lhs_seen, mut rhs_seen) = (self.seen, other.seen);
+
+        self.seen += other.seen;
+
+        while self.items.len() < self.capacity && (lhs.len() > 0 || rhs.len() > 0) {
+            let from_lhs = rhs.len() == 0 ||
+                (lhs.len() > 0 && self.random(lhs_seen + rhs_seen) < lhs_seen);
+
+            if from_lhs {
+                self.items.push(lhs.pop().unwrap());
+                lhs_seen = lhs_seen.saturating_sub(1);
+            } else {
+                self.items.push(rhs.pop().unwrap());
+                rhs_seen = rhs_seen.saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn items(&self) -> &Vec<T> {
+        &self.items
+    }
+}
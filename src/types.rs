@@ -1,71 +1,298 @@
-use chrono::TimeZone;
-use chrono::prelude::Utc;
-use std::sync::Mutex;
 use regex::Regex;
-use std::time::Duration;
+use std::collections::{BTreeMap, HashSet};
+use sql;
+use regs;
+use expr::Expr;
+use datetime::Zone;
+use baseline::{Baseline, Threshold};
+
+/// Extended header fields of Percona Server and MySQL 8 log_slow_extra, lowercased
+pub const EXTENDED_FIELDS: &'static [&'static str] = &[
+    "thread_id", "last_errno", "errno", "killed", "bytes_received", "bytes_sent",
+    "read_first", "read_last", "read_key", "read_next", "read_prev", "read_rnd", "read_rnd_next",
+    "sort_merge_passes", "sort_range_count", "sort_rows", "sort_scan_count",
+    "created_tmp_disk_tables", "created_tmp_tables", "start", "end",
+    "tmp_tables", "tmp_disk_tables", "tmp_table_sizes", "innodb_trx_id",
+    "qc_hit", "full_scan", "full_join", "tmp_table", "tmp_table_on_disk",
+    "filesort", "filesort_on_disk", "merge_passes",
+    "innodb_io_r_ops", "innodb_io_r_bytes", "innodb_io_r_wait",
+    "innodb_rec_lock_wait", "innodb_queue_wait", "innodb_pages_distinct"
+];
+
+pub enum SortField {
+    Timestamp,
+    QueryTime,
+    LockTime,
+    RowsSent,
+    RowsExamined,
+    RowsAffected,
+    Db,
+    User,
+    Host,
+    Ip,
+    Statement,
+    Fingerprint,
+    Tag(String),
+    Extended(String),
+    Count,
+    TotalTime,
+    AvgTime,
+    MaxTime,
+    TotalExamined,
+    ExaminedRatio
+}
 
-struct QueryStore {
-    pub db: String,
-    pub timestamp: i64
+impl SortField {
+    pub fn parse(name: &str) -> Option<SortField> {
+        let lower = name.to_lowercase();
+
+        match &*lower {
+            "ts"      => Some(SortField::Timestamp),
+            "qt"      => Some(SortField::QueryTime),
+            "lt"      => Some(SortField::LockTime),
+            "rs"      => Some(SortField::RowsSent),
+            "re"      => Some(SortField::RowsExamined),
+            "ra"      => Some(SortField::RowsAffected),
+            "db"      => Some(SortField::Db),
+            "user"    => Some(SortField::User),
+            "host"    => Some(SortField::Host),
+            "ip"      => Some(SortField::Ip),
+            "stmt"    => Some(SortField::Statement),
+            "fp"      => Some(SortField::Fingerprint),
+            "cnt"     => Some(SortField::Count),
+            "sum_qt"  => Some(SortField::TotalTime),
+            "avg_qt"  => Some(SortField::AvgTime),
+            "max_qt"  => Some(SortField::MaxTime),
+            "sum_re"  => Some(SortField::TotalExamined),
+            "re_rs"   => Some(SortField::ExaminedRatio),
+            _ if lower.starts_with("tag:") && name.len() > 4 => Some(SortField::Tag(name[4..].to_string())),
+            _ if EXTENDED_FIELDS.contains(&&*lower) => Some(SortField::Extended(lower)),
+            _ => None
+        }
+    }
 }
 
-impl QueryStore {
-    pub fn new() -> Self {
-        Self {
-            db: "?".to_string(),
-            timestamp: -1
+impl ToString for SortField {
+    fn to_string(&self) -> String {
+        match self {
+            &SortField::Timestamp => "ts".to_string(),
+            &SortField::QueryTime => "qt".to_string(),
+            &SortField::LockTime => "lt".to_string(),
+            &SortField::RowsSent => "rs".to_string(),
+            &SortField::RowsExamined => "re".to_string(),
+            &SortField::RowsAffected => "ra".to_string(),
+            &SortField::Db => "db".to_string(),
+            &SortField::User => "user".to_string(),
+            &SortField::Host => "host".to_string(),
+            &SortField::Ip => "ip".to_string(),
+            &SortField::Statement => "stmt".to_string(),
+            &SortField::Fingerprint => "fp".to_string(),
+            &SortField::Tag(ref key) => format!("tag:{}", key),
+            &SortField::Extended(ref name) => name.clone(),
+            &SortField::Count => "cnt".to_string(),
+            &SortField::TotalTime => "sum_qt".to_string(),
+            &SortField::AvgTime => "avg_qt".to_string(),
+            &SortField::MaxTime => "max_qt".to_string(),
+            &SortField::TotalExamined => "sum_re".to_string(),
+            &SortField::ExaminedRatio => "re_rs".to_string()
         }
     }
 }
 
-lazy_static! {
-    static ref store: Mutex<QueryStore> = Mutex::new(QueryStore::new());
+/// One key of the sort order, keys are compared in order until they differ
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool
 }
 
-pub enum QueriesSortType {
-    Timestamp = 0,
-    QueryTime,
-    LockTime,
-    RowsSent,
-    RowsExamined,
-    RowsAffected,
-    TimestampInverse,
-    QueryTimeInverse,
-    LockTimeInverse,
-    RowsSentInverse,
-    RowsExaminedInverse,
-    RowsAffectedInverse,
-    Count,
-    CountInverse,
+impl SortKey {
+    /// Parses FIELD[:asc|:desc], and legacy tsi, qti, ..., cnti for descending order
+    pub fn parse(key: &str) -> Option<SortKey> {
+        let key = key.trim();
+        let mut key_split = key.rsplitn(2, ':');
+        let last = key_split.next().unwrap_or("");
+        let rest = key_split.next();
+
+        let (name, descending) = match (rest, &*last.to_lowercase()) {
+            (Some(name), "asc") => (name, false),
+            (Some(name), "desc") => (name, true),
+            _ => match key {
+                "tsi" | "qti" | "lti" | "rsi" | "rei" | "rai" | "cnti" => (&key[..key.len() - 1], true),
+                _ => (key, false)
+            }
+        };
+
+        SortField::parse(name).map(|field| SortKey { field, descending })
+    }
+}
+
+impl ToString for SortKey {
+    fn to_string(&self) -> String {
+        format!("{}:{}", self.field.to_string(), if self.descending { "desc" } else { "asc" })
+    }
+}
+
+pub enum QueryView {
+    Query = 0,
+    Raw,
+    Fingerprint,
+    Sample,
+    Both,
+    Undefined
+}
+
+impl ToString for QueryView {
+    fn to_string(&self) -> String {
+        match self {
+            &QueryView::Query => "Query".to_string(),
+            &QueryView::Raw => "Raw query".to_string(),
+            &QueryView::Fingerprint => "Fingerprint".to_string(),
+            &QueryView::Sample => "Fingerprint with sample".to_string(),
+            &QueryView::Both => "Fingerprint and raw query".to_string(),
+            &QueryView::Undefined => "Undefined".to_string()
+        }
+    }
+}
+
+pub enum OutputFormat {
+    Text = 0,
+    Json
+}
+
+impl ToString for OutputFormat {
+    fn to_string(&self) -> String {
+        match self {
+            &OutputFormat::Text => "Text".to_string(),
+            &OutputFormat::Json => "JSON".to_string()
+        }
+    }
+}
+
+pub enum ReportType {
+    Tables = 0,
+    Summary,
+    Concurrency,
+    Locks,
+    Sessions,
+    NPlusOne,
+    Undefined
+}
+
+impl ToString for ReportType {
+    fn to_string(&self) -> String {
+        match self {
+            &ReportType::Tables => "Tables".to_string(),
+            &ReportType::Summary => "Summary".to_string(),
+            &ReportType::Concurrency => "Concurrency".to_string(),
+            &ReportType::Locks => "Locks".to_string(),
+            &ReportType::Sessions => "Sessions".to_string(),
+            &ReportType::NPlusOne => "N+1".to_string(),
+            &ReportType::Undefined => "Undefined".to_string()
+        }
+    }
+}
+
+pub enum GroupDim {
+    Db,
+    User,
+    Host,
+    Ip,
+    Statement,
+    Table,
+    Hour,
+    Tag(String),
     Undefined
 }
 
-impl ToString for QueriesSortType {
+impl ToString for GroupDim {
+    fn to_string(&self) -> String {
+        match self {
+            &GroupDim::Db => "DB".to_string(),
+            &GroupDim::User => "USER".to_string(),
+            &GroupDim::Host => "HOST".to_string(),
+            &GroupDim::Ip => "IP".to_string(),
+            &GroupDim::Statement => "STATEMENT".to_string(),
+            &GroupDim::Table => "TABLE".to_string(),
+            &GroupDim::Hour => "HOUR".to_string(),
+            &GroupDim::Tag(ref key) => format!("TAG:{}", key),
+            &GroupDim::Undefined => "UNDEFINED".to_string()
+        }
+    }
+}
+
+/// Query anti-pattern checked by the linter
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LintRule {
+    SelectStar,
+    LeadingWildcard,
+    OrderByRand,
+    NoWhere,
+    FunctionOnColumn,
+    LargeOffset,
+    NotInSubquery,
+    CrossJoin
+}
+
+impl LintRule {
+    pub const ALL: &'static [LintRule] = &[
+        LintRule::SelectStar,
+        LintRule::LeadingWildcard,
+        LintRule::OrderByRand,
+        LintRule::NoWhere,
+        LintRule::FunctionOnColumn,
+        LintRule::LargeOffset,
+        LintRule::NotInSubquery,
+        LintRule::CrossJoin
+    ];
+
+    pub fn parse(name: &str) -> Option<LintRule> {
+        LintRule::ALL.iter().find(|rule| rule.to_string() == name).cloned()
+    }
+}
+
+impl ToString for LintRule {
     fn to_string(&self) -> String {
         match self {
-            &QueriesSortType::Timestamp => "Timestamp".to_string(),
-            &QueriesSortType::QueryTime => "Query time".to_string(),
-            &QueriesSortType::LockTime => "Lock time".to_string(),
-            &QueriesSortType::RowsSent => "Rows sent".to_string(),
-            &QueriesSortType::RowsExamined => "Rows examined".to_string(),
-            &QueriesSortType::RowsAffected => "Rows affected".to_string(),
-            &QueriesSortType::TimestampInverse => "Timestamp inverse".to_string(),
-            &QueriesSortType::QueryTimeInverse => "Query time inverse".to_string(),
-            &QueriesSortType::LockTimeInverse => "Lock time inverse".to_string(),
-            &QueriesSortType::RowsSentInverse => "Rows sent inverse".to_string(),
-            &QueriesSortType::RowsExaminedInverse => "Rows examined inverse".to_string(),
-            &QueriesSortType::RowsAffectedInverse => "Rows affected inverse".to_string(),
-            &QueriesSortType::Count => "Count".to_string(),
-            &QueriesSortType::CountInverse => "Count inverse".to_string(),
-            &QueriesSortType::Undefined => "Undefined".to_string()
+            &LintRule::SelectStar => "select_star".to_string(),
+            &LintRule::LeadingWildcard => "leading_wildcard".to_string(),
+            &LintRule::OrderByRand => "order_by_rand".to_string(),
+            &LintRule::NoWhere => "no_where".to_string(),
+            &LintRule::FunctionOnColumn => "function_on_column".to_string(),
+            &LintRule::LargeOffset => "large_offset".to_string(),
+            &LintRule::NotInSubquery => "not_in_subquery".to_string(),
+            &LintRule::CrossJoin => "cross_join".to_string()
+        }
+    }
+}
+
+/// Which query of the same fingerprint is kept on deduplication
+pub enum DedupKeep {
+    Slowest,
+    Examined,
+    First,
+    Last
+}
+
+impl ToString for DedupKeep {
+    fn to_string(&self) -> String {
+        match self {
+            &DedupKeep::Slowest => "slowest".to_string(),
+            &DedupKeep::Examined => "examined".to_string(),
+            &DedupKeep::First => "first".to_string(),
+            &DedupKeep::Last => "last".to_string()
         }
     }
 }
 
 pub struct Config {
     pub log_file: String,
+    pub state_file: String,
     pub timestamp_begin: i64,
     pub timestamp_end: i64,
+    pub last: i64,
+    pub timezone: Zone,
+    pub display_timezone: Zone,
+    pub dt_format: String,
     pub db: String,
     pub query_time_min: f64,
     pub query_time_max: f64,
@@ -77,25 +304,50 @@ pub struct Config {
     pub rows_examined_max: i64,
     pub rows_affected_min: i64,
     pub rows_affected_max: i64,
-    pub sort_type: QueriesSortType,
-    pub regex: Option<Regex>,
+    pub sort: Vec<SortKey>,
+    pub query_view: QueryView,
+    pub format: OutputFormat,
+    pub regexes: Vec<Regex>,
+    pub exclude_regexes: Vec<Regex>,
+    pub tags: Vec<(String, Option<String>)>,
+    pub tables: Vec<String>,
+    pub reports: Vec<ReportType>,
+    pub group_by: Vec<GroupDim>,
+    pub where_expr: Option<Expr>,
     pub count_min: usize,
     pub count_max: usize,
     pub limit: usize,
     pub abs: bool,
     pub web_addr: String,
     pub web_port: u16,
-    pub dedup: bool,
-    pub wpd: Duration,
-    errors: Vec<&'static str>
+    pub dedup: Option<DedupKeep>,
+    pub lint: Vec<LintRule>,
+    pub burst_min: usize,
+    pub burst_window: i64,
+    pub save_baseline: String,
+    pub baseline_file: String,
+    pub baseline: Option<Baseline>,
+    pub anomaly: Threshold,
+    pub fail_if: Vec<Expr>,
+    pub allowlist_file: String,
+    pub allowlist: Option<HashSet<String>>,
+    pub max_fp_time: f64,
+    pub aggregate: bool,
+    pub threads: usize,
+    errors: Vec<String>
 }
 
 impl Config {
     pub fn new() -> Self {
         Self {
             log_file: "mysql-slow.log".to_string(),
+            state_file: String::new(),
             timestamp_begin: -1,
             timestamp_end: -1,
+            last: 0,
+            timezone: Zone::utc(),
+            display_timezone: Zone::utc(),
+            dt_format: Query::DT_FORMAT.to_string(),
             db: "".to_string(),
             query_time_min: -1.0,
             query_time_max: -1.0,
@@ -107,29 +359,62 @@ impl Config {
             rows_examined_max: -1,
             rows_affected_min: -1,
             rows_affected_max: -1,
-            sort_type: QueriesSortType::Undefined,
-            regex: None,
+            sort: Vec::new(),
+            query_view: QueryView::Undefined,
+            format: OutputFormat::Text,
+            regexes: Vec::new(),
+            exclude_regexes: Vec::new(),
+            tags: Vec::new(),
+            tables: Vec::new(),
+            reports: Vec::new(),
+            group_by: Vec::new(),
+            where_expr: None,
             count_min: 0,
             count_max: 0,
             limit: 0,
             abs: false,
             web_addr: String::new(),
             web_port: 0,
-            dedup: false,
-            wpd: Duration::from_millis(1),
+            dedup: None,
+            lint: Vec::new(),
+            burst_min: 10,
+            burst_window: 1,
+            save_baseline: String::new(),
+            baseline_file: String::new(),
+            baseline: None,
+            anomaly: Threshold::ZScore(3.0),
+            fail_if: Vec::new(),
+            allowlist_file: String::new(),
+            allowlist: None,
+            max_fp_time: -1.0,
+            aggregate: false,
+            threads: 1,
             errors: Vec::new()
         }
     }
 
-    pub fn add_error(&mut self, err: &'static str) {
+    pub fn add_error(&mut self, err: &str) {
         let err_index = self.errors.len();
-        self.errors.insert(err_index, err);
+        self.errors.insert(err_index, err.to_string());
     }
 
     pub fn has_errors(&self) -> bool {
         self.errors.len() != 0
     }
 
+    /// Whether any CI gate check is set
+    pub fn gate(&self) -> bool {
+        self.fail_if.len() > 0 || self.allowlist.is_some() || self.max_fp_time >= 0.0
+    }
+
+    pub fn json(&self) -> bool {
+        if let OutputFormat::Json = self.format { true } else { false }
+    }
+
+    pub fn format_timestamp(&self, timestamp: i64) -> String {
+        self.display_timezone.format(timestamp, &self.dt_format)
+    }
+
     pub fn errors(&self) -> String {
         let mut errors_string = "\t".to_string();
 
@@ -143,37 +428,83 @@ impl ToString for Config {
     fn to_string(&self) -> String {
         format!("CONFIGURATION:
 \tLog file: \"{}\"
+\tState file: \"{}\"
 \tDatabase: \"{}\"
 \tTimestamp range: {} - {}
+\tLast: {}s
+\tTimezone: {}
+\tDisplay timezone: {}
+\tDate and time format: \"{}\"
 \tQuery time range: {} - {}
 \tLock time range: {} - {}
 \tRows sent range: {} - {}
 \tRows examined range: {} - {}
 \tRows affected range: {} - {}
-\tSort type: {}
-\tQuery regex: {:?}
+\tSort: {}
+\tQuery view: {}
+\tOutput format: {}
+\tQuery regexes: {:?}
+\tExclude regexes: {:?}
+\tComment tags: {:?}
+\tTables: {:?}
+\tReports: {:?}
+\tGroup by: {:?}
+\tWhere: {}
 \tCount range: {} - {}
 \tLimit: first {}
 \tStr & num abstract: {}
 \tWeb address: \"{}\"
 \tWeb port: {}
-\tDeduplication: {}",
+\tDeduplication: {}
+\tLint rules: {:?}
+\tN+1 bursts: {} queries, {}s apart
+\tSave baseline: \"{}\"
+\tBaseline: \"{}\"
+\tAnomaly threshold: {}
+\tFail if: {:?}
+\tFingerprint allowlist: \"{}\"
+\tMax fingerprint time: {}
+\tAggregate: {}
+\tThreads: {}",
         self.log_file,
+        self.state_file,
         self.db,
         self.timestamp_begin, self.timestamp_end,
+        self.last,
+        self.timezone.to_string(),
+        self.display_timezone.to_string(),
+        self.dt_format,
         self.query_time_min, self.query_time_max,
         self.lock_time_min, self.lock_time_max,
         self.rows_sent_min, self.rows_sent_max,
         self.rows_examined_min, self.rows_examined_max,
         self.rows_affected_min, self.rows_affected_max,
-        self.sort_type.to_string(),
-        self.regex,
+        self.sort.iter().map(|key| key.to_string()).collect::<Vec<String>>().join(","),
+        self.query_view.to_string(),
+        self.format.to_string(),
+        self.regexes.iter().map(|regex| regex.as_str()).collect::<Vec<&str>>(),
+        self.exclude_regexes.iter().map(|regex| regex.as_str()).collect::<Vec<&str>>(),
+        self.tags,
+        self.tables,
+        self.reports.iter().map(|report| report.to_string()).collect::<Vec<String>>(),
+        self.group_by.iter().map(|dim| dim.to_string()).collect::<Vec<String>>(),
+        self.where_expr.as_ref().map(|expr| expr.to_string()).unwrap_or_else(|| "-".to_string()),
         self.count_min, self.count_max,
         if self.limit < super::std::usize::MAX { self.limit + 1 } else { self.limit },
         self.abs,
         self.web_addr,
         self.web_port,
-        self.dedup)
+        self.dedup.as_ref().map(|keep| keep.to_string()).unwrap_or_else(|| "-".to_string()),
+        self.lint.iter().map(|rule| rule.to_string()).collect::<Vec<String>>(),
+        self.burst_min, self.burst_window,
+        self.save_baseline,
+        self.baseline_file,
+        self.anomaly.to_string(),
+        self.fail_if.iter().map(|expr| expr.to_string()).collect::<Vec<String>>(),
+        self.allowlist_file,
+        self.max_fp_time,
+        self.aggregate,
+        self.threads)
     }
 }
 
@@ -181,12 +512,23 @@ impl ToString for Config {
 pub struct Query {
     pub timestamp: i64,
     pub db: String,
+    pub user: String,
+    pub host: String,
+    pub ip: String,
+    pub connection_id: i64,
     pub query_time: f64,
     pub lock_time: f64,
     pub rows_sent: i64,
     pub rows_examined: i64,
     pub rows_affected: i64,
     pub query: String,
+    pub raw_query: String,
+    pub fingerprint: String,
+    pub tags: BTreeMap<String, String>,
+    pub extended: BTreeMap<String, String>,
+    pub hints: Vec<String>,
+    pub statement: String,
+    pub tables: Vec<String>,
     pub consuming_query: bool,
     pub query_consumed: bool
 }
@@ -198,57 +540,121 @@ impl Query {
         Self {
             timestamp: -1,
             db: "?".to_string(),
+            user: "?".to_string(),
+            host: "?".to_string(),
+            ip: "?".to_string(),
+            connection_id: -1,
             query_time: -1.0,
             lock_time: -1.0,
             rows_sent: -1,
             rows_examined: -1,
             rows_affected: -1,
             query: "?".to_string(),
+            raw_query: String::new(),
+            fingerprint: String::new(),
+            tags: BTreeMap::new(),
+            extended: BTreeMap::new(),
+            hints: Vec::new(),
+            statement: "?".to_string(),
+            tables: Vec::new(),
             consuming_query: false,
             query_consumed: false
         }
     }
 
+    /// When execution started, derived from end timestamp and query time.
+    /// Lock waits are taken to happen at the start of execution
+    pub fn start(&self) -> f64 {
+        self.timestamp as f64 - self.query_time.max(0.0)
+    }
+
+    pub fn has_tag(&self, key: &String, value: &Option<String>) -> bool {
+        match (self.tags.get(key), value) {
+            (Some(tag_value), &Some(ref value)) => tag_value == value,
+            (Some(_), &None) => true,
+            _ => false
+        }
+    }
+
+    pub fn has_table(&self, table: &String) -> bool {
+        self.tables.iter().any(|name|
+            name == table || name.rsplit('.').next().map(|short| short == table).unwrap_or(false))
+    }
+
+    /// Numeric value of extended field, Yes and No flags are 1 and 0
+    pub fn extended_value(&self, name: &str) -> Option<f64> {
+        self.extended.get(name).and_then(|value| match &**value {
+            "Yes" => Some(1.0),
+            "No" => Some(0.0),
+            _ => value.parse::<f64>().ok()
+        })
+    }
+
     pub fn valid(&self) -> bool {
         self.query_consumed && self.query != "?"
     }
 
+    /// Fingerprint of query text as printed, shared by parser and allowlist
+    pub fn fingerprint_of(query: &String) -> String {
+        let mut fingerprint = regs::remove_comments(query);
+
+        fingerprint = regs::abs_numbers(&fingerprint);
+        fingerprint = regs::abs_strings(&fingerprint);
+        fingerprint = regs::prs_spaces_trim(&fingerprint);
+
+        Self::collapse_spaces(&fingerprint)
+    }
+
     pub fn finish(&mut self) {
-        let mut st = store.lock().unwrap();
+        self.query = Self::collapse_spaces(&self.query);
 
-        if self.db == "?" {
-            self.db = st.db.clone();
-        } else {
-            st.db = self.db.clone();
+        if self.raw_query.ends_with('\n') {
+            let raw_len = self.raw_query.len() - 1;
+            self.raw_query.truncate(raw_len);
         }
 
-        if self.timestamp < 0 {
-            self.timestamp = st.timestamp;
-        } else {
-            st.timestamp = self.timestamp;
-        }
+        let tokens = sql::tokenize(&self.query);
+
+        self.statement = sql::statement_type(&tokens);
+        self.tables = sql::tables(&tokens);
+    }
 
-        self.query = self.query
+    fn collapse_spaces(query: &String) -> String {
+        let mut ret = query
             .replace("\r", " ")
             .replace("\n", " ")
             .replace("\t", " ")
             .trim().to_string();
 
-        while self.query.contains("  ") {
-            self.query = self.query.replace("  ", " ");
+        while ret.contains("  ") {
+            ret = ret.replace("  ", " ");
         }
 
-        while self.query.contains(",|STRING|") {
-            self.query = self.query.replace(",|STRING|", ", |STRING|");
+        while ret.contains(",|STRING|") {
+            ret = ret.replace(",|STRING|", ", |STRING|");
         }
+
+        ret
     }
 
-    pub fn to_string(&self, index: usize, count: usize) -> String {
+    pub fn query_text(&self, view: &QueryView, sample: Option<&String>) -> String {
+        match view {
+            &QueryView::Raw => self.raw_query.clone(),
+            &QueryView::Fingerprint => self.fingerprint.clone(),
+            &QueryView::Sample =>
+                format!("{}\n--- EXAMPLE:\n{}", self.fingerprint, sample.unwrap_or(&self.raw_query)),
+            &QueryView::Both =>
+                format!("{}\n--- RAW:\n{}", self.fingerprint, self.raw_query),
+            _ => self.query.clone()
+        }
+    }
+
+    /// Entry lines above the query text
+    pub fn header(&self, index: usize, count: usize, cnf: &Config) -> String {
         let mut buf = format!("> #{} | DATE_TIME: ", index.to_string());
 
         if self.timestamp >= 0 {
-            let date_time = Utc.timestamp(self.timestamp, 0);
-            buf.push_str(&date_time.format(Self::DT_FORMAT).to_string());
+            buf.push_str(&cnf.format_timestamp(self.timestamp));
         } else {
             buf.push_str("?");
         }
@@ -294,7 +700,17 @@ impl Query {
         }
 
         buf.push_str(&format!(" | COUNT: {}", count.to_string()));
-        buf.push_str(&format!("\n{}\n", self.query));
+
+        if self.tags.len() > 0 {
+            let tags: Vec<String> = self.tags.iter()
+                .map(|(key, value)| format!("{}={}", key, value)).collect();
+
+            buf.push_str(&format!("\n>>>> TAGS: {}", tags.join(", ")));
+        }
+
+        if self.hints.len() > 0 {
+            buf.push_str(&format!("\n>>>> HINTS: {}", self.hints.join(" ")));
+        }
 
         buf
     }
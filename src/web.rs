@@ -1,76 +1,91 @@
-use super::{read_queries, config, queries};
+use super::read_queries;
 
-use types::{Query};
-use std::sync::Mutex;
+use types::Config;
+use std::fs;
+use std::sync::{Arc, RwLock};
 use std::thread::{self, sleep};
-use processing::{self, qhash};
+use std::time::{Duration, SystemTime};
+use processing::{self, Snapshot};
+use reports;
+use groups;
+use baseline;
 use rocket;
-use rocket::config::{Config, Environment};
-use chrono::Utc;
+use rocket::State;
+use rocket::config::{self, Environment};
 
-lazy_static! {
-    pub static ref wqq: Mutex<Vec<Query>> = Mutex::new(Vec::new());
-}
+/// Log is checked for changes with this interval (seconds)
+const REFRESH_INTERVAL: u64 = 1;
 
-lazy_static! {
-    static ref old_response: Mutex<String> = Mutex::new("Please refresh page later".to_string());
-}
+/// Latest processing result. Requests clone the pointer and render
+/// without holding the lock, the update thread swaps in a new one.
+struct Current(RwLock<Arc<Snapshot>>);
 
+// Rocket hands request guards over by value
 #[get("/")]
-fn all() -> String {
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn all(current: State<Arc<Current>>, cnf: State<Arc<Config>>) -> String {
     let mut response: Vec<String> = Vec::new();
-    let web_queries = wqq.lock().unwrap();
+    let snapshot = current.0.read().unwrap().clone();
+    for (index, query) in snapshot.shown().into_iter().enumerate() {
+        response.push(snapshot.render(index + 1, query, &cnf));
+    }
 
-    let queries_hash = if let Ok(qh_lock) = qhash.try_lock() {
-        qh_lock
-    } else {
-        println!("[{}] WEB: Previous result provided (unable to lock queries count)", Utc::now().to_string());
-        return old_response.lock().unwrap().clone();
-    };
+    for report in cnf.reports.iter() {
+        response.push(reports::render(report, &snapshot.queries, &cnf));
+    }
 
-    let (count_min, count_max) = {
-        let cnf = config.lock().unwrap();
-        (cnf.count_min, cnf.count_max)
-    };
+    if cnf.group_by.len() > 0 {
+        response.push(groups::report(&snapshot.queries, &cnf));
+    }
 
-    for (index, query) in web_queries.iter().enumerate() {
-        let count = {
-            if let Some(qcount) = queries_hash.get(&query.query) {
-                *qcount
-            } else {
-                1
-            }
-        };
+    if cnf.lint.len() > 0 {
+        response.push(snapshot.findings.to_string());
+    }
 
-        if count >= count_min && count <= count_max {
-            response.push(query.to_string(index + 1, count));
+    if let Some(ref profile) = snapshot.profile {
+        if cnf.baseline.is_some() {
+            response.push(baseline::report(profile, &cnf));
         }
     }
 
-    let mut res = old_response.lock().unwrap();
+    response.join("\n")
+}
 
-    *res = response.join("\n");
-    res.clone()
+fn log_version(log_file: &String) -> Option<(u64, Option<SystemTime>)> {
+    fs::metadata(log_file).ok().map(|meta| (meta.len(), meta.modified().ok()))
 }
 
-pub fn invoke_web() {
-    let update_thread = thread::spawn(move || {
-        let wdelay = config.lock().unwrap().wpd;
+pub fn invoke_web(cnf: Arc<Config>, snapshot: Snapshot) {
+    let current = Arc::new(Current(RwLock::new(Arc::new(snapshot))));
 
-        loop {
-            read_queries(true);
-            let mut qq = queries.lock().unwrap();
-            processing::process(&mut qq, true);
-            sleep(wdelay * 10);
-        }
-    });
+    let update_thread = {
+        let (cnf, current) = (cnf.clone(), current.clone());
 
-    let rocket_config = {
-        let cnf = config.lock().unwrap();
+        thread::spawn(move || {
+            let mut version = log_version(&cnf.log_file);
+
+            loop {
+                sleep(Duration::from_secs(REFRESH_INTERVAL));
+
+                let new_version = log_version(&cnf.log_file);
+
+                if new_version == version {
+                    continue;
+                }
+
+                version = new_version;
 
+                let snapshot = processing::process(read_queries(&cnf, true).unwrap_or_default(), &cnf);
+
+                *current.0.write().unwrap() = Arc::new(snapshot);
+            }
+        })
+    };
+
+    let rocket_config = {
         println!("\nWeb server running on {}:{}", cnf.web_addr.clone(), cnf.web_port);
 
-        Config::build(Environment::Production)
+        config::Config::build(Environment::Production)
             .address(cnf.web_addr.clone())
             .port(cnf.web_port)
             .workers(10)
@@ -78,6 +93,8 @@ pub fn invoke_web() {
     };
 
     rocket::custom(rocket_config, false)
+        .manage(current)
+        .manage(cnf)
         .mount("/", routes![all])
         .launch();
 
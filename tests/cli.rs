@@ -0,0 +1,609 @@
+// &Vec parameters and len() comparisons as in the crate, query times are
+// compared exactly as printed
+#![cfg_attr(feature = "cargo-clippy", allow(ptr_arg, len_zero, float_cmp))]
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// (seconds after the first entry, query time, query), in log order
+const ENTRIES: &[(i64, f64, &str)] = &[
+    (0, 0.10, "SELECT * FROM users WHERE id = 1;"),
+    (1, 1.00, "SELECT * FROM orders WHERE id = 2;"),
+    (2, 0.20, "SELECT * FROM users WHERE id = 1;"),
+    (3, 0.05, "UPDATE stock SET qty = qty - 1 WHERE id = 3;"),
+    (4, 5.00, "DELETE FROM sessions WHERE expired = 1;"),
+    (5, 0.30, "SELECT * FROM users WHERE id = 1;"),
+    (6, 2.00, "SELECT * FROM orders WHERE id = 2;"),
+    (7, 0.40, "SELECT * FROM users WHERE id = 1;"),
+    (8, 0.06, "UPDATE stock SET qty = qty - 1 WHERE id = 3;"),
+    (9, 3.00, "SELECT * FROM orders WHERE id = 2;"),
+    (10, 0.50, "SELECT * FROM users WHERE id = 1;")
+];
+
+const TIMESTAMP: i64 = 1_536_746_400;
+
+struct Entry {
+    query_time: f64,
+    rows_examined: i64,
+    count: usize,
+    group: Option<String>,
+    query: String
+}
+
+struct Output {
+    entries: Vec<Entry>,
+    total: usize,
+    filtered: usize,
+    duplicates: usize,
+    shown: usize
+}
+
+/// Writes entries of (seconds after the first entry, query time, rows examined, query)
+fn write_log(name: &str, entries: &[(i64, f64, i64, &str)]) -> PathBuf {
+    let path = env::temp_dir().join(format!("myslowparser-{}-{}.log", name, std::process::id()));
+    let mut file = File::create(&path).unwrap();
+
+    for &(offset, query_time, rows_examined, query) in entries.iter() {
+        write!(file, "# Time: 2018-09-12T10:00:{:02}.000000Z\n\
+                      # User@Host: app[app] @ web1 [10.0.0.5]  Id:    12\n\
+                      # Query_time: {:.6}  Lock_time: 0.000000 Rows_sent: 1  Rows_examined: {}\n\
+                      SET timestamp={};\n{}\n",
+               offset, query_time, rows_examined, TIMESTAMP + offset, query).unwrap();
+    }
+
+    path
+}
+
+fn log_file(name: &str) -> PathBuf {
+    let entries: Vec<(i64, f64, i64, &str)> = ENTRIES.iter()
+        .map(|&(offset, query_time, query)| (offset, query_time, 10, query))
+        .collect();
+
+    write_log(name, &entries)
+}
+
+fn binary() -> PathBuf {
+    let mut path = env::current_exe().unwrap();
+
+    path.pop();
+
+    if path.ends_with("deps") {
+        path.pop();
+    }
+
+    path.join("myslowparser")
+}
+
+fn field<'a>(line: &'a str, name: &str) -> &'a str {
+    let start = line.find(&format!("{}: ", name)).unwrap() + name.len() + 2;
+
+    line[start..].split(" | ").next().unwrap().trim()
+}
+
+fn run(log: &PathBuf, args: &Vec<String>) -> Output {
+    let output = Command::new(binary())
+        .arg("-f").arg(log)
+        .args(args)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{:?} failed", args);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    let mut parsed = Output { entries: Vec::new(), total: 0, filtered: 0, duplicates: 0, shown: 0 };
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+
+        if line.starts_with("> #") {
+            let mut entry = Entry {
+                query_time: field(lines[index + 1], "QUERY_TIME").parse().unwrap(),
+                rows_examined: field(lines[index + 1], "ROWS_EXAMINED").parse().unwrap(),
+                count: field(lines[index + 2], "COUNT").parse().unwrap(),
+                group: None,
+                query: String::new()
+            };
+
+            index += 3;
+
+            while lines[index].starts_with(">>>> ") {
+                if lines[index].starts_with(">>>> GROUP_COUNT: ") {
+                    entry.group = Some(lines[index][5..].to_string());
+                }
+
+                index += 1;
+            }
+
+            entry.query = lines[index].to_string();
+            parsed.entries.push(entry);
+        } else if line.starts_with("TOTAL: ") {
+            parsed.total = line[7..].parse().unwrap();
+        } else if line.starts_with("FILTERED: ") {
+            parsed.filtered = line[10..].parse().unwrap();
+        } else if line.starts_with("DUPLICATES: ") {
+            parsed.duplicates = line[12..].parse().unwrap();
+        } else if line.starts_with("SHOWN: ") {
+            parsed.shown = line[7..].parse().unwrap();
+        }
+
+        index += 1;
+    }
+
+    parsed
+}
+
+fn count(query: &str) -> usize {
+    ENTRIES.iter().filter(|entry| entry.2 == query).count()
+}
+
+/// (seconds after the first entry, query time, query, index in ENTRIES)
+type Row = (usize, f64, String, usize);
+
+/// What the parser should print, worked out straight from ENTRIES
+fn expected(qt_min: f64, regex: Option<&str>, count_range: (usize, usize), dedup: bool,
+            limit: Option<usize>, sort: &str) -> (Vec<Row>, usize, usize) {
+    let mut entries: Vec<Row> = ENTRIES.iter().enumerate()
+        .map(|(index, &(offset, query_time, query))| (offset as usize, query_time, query.to_string(), index))
+        .collect();
+
+    let before = entries.len();
+
+    entries.retain(|entry| {
+        let count = count(&entry.2);
+
+        entry.1 >= qt_min &&
+            regex.map(|regex| regex.split('|').any(|word| entry.2.contains(word))).unwrap_or(true) &&
+            count >= count_range.0 && count <= count_range.1
+    });
+
+    let filtered = before - entries.len();
+    let passed = entries.len();
+
+    if dedup {
+        entries = entries.iter().enumerate()
+            .filter(|&(index, entry)| !entries[index + 1..].iter().any(|later| later.2 == entry.2))
+            .map(|(_, entry)| entry.clone())
+            .collect();
+    }
+
+    let duplicates = passed - entries.len();
+
+    entries.sort_by(|lhs, rhs| {
+        let ordering = match sort {
+            "qt:desc" => rhs.1.partial_cmp(&lhs.1).unwrap(),
+            "qti" => rhs.1.partial_cmp(&lhs.1).unwrap(),
+            "cnt:desc" => count(&rhs.2).cmp(&count(&lhs.2)),
+            "cnt,qt:desc" => count(&lhs.2).cmp(&count(&rhs.2)).then(rhs.1.partial_cmp(&lhs.1).unwrap()),
+            _ => ::std::cmp::Ordering::Equal
+        };
+
+        ordering.then(lhs.0.cmp(&rhs.0))
+    });
+
+    if let Some(limit) = limit {
+        entries.truncate(limit);
+    }
+
+    (entries, filtered, duplicates)
+}
+
+/// (options, query time minimum, regex words, count range)
+type Filter<'a> = (Vec<&'a str>, f64, Option<&'a str>, (usize, usize));
+
+#[test]
+fn filters_dedup_limit_and_sort_combinations() {
+    let log = log_file("combinations");
+    let filters: Vec<Filter> = vec![
+        (vec![], -1.0, None, (0, usize::max_value())),
+        (vec!["--qt_min", "0.3"], 0.3, None, (0, usize::max_value())),
+        (vec!["-r", "users|orders"], -1.0, Some("users|orders"), (0, usize::max_value())),
+        (vec!["--cnt_min", "2"], -1.0, None, (2, usize::max_value())),
+        (vec!["--cnt_max", "3"], -1.0, None, (0, 3)),
+        (vec!["--cnt_min", "2", "--cnt_max", "3"], -1.0, None, (2, 3)),
+        (vec!["--cnt_min", "3", "--qt_min", "0.3"], 0.3, None, (3, usize::max_value()))
+    ];
+
+    for &(ref filter_args, qt_min, regex, count_range) in filters.iter() {
+        for &dedup in [false, true].iter() {
+            for &limit in [None, Some(1), Some(3), Some(100)].iter() {
+                for &sort in ["ts", "qt:desc", "qti", "cnt:desc", "cnt,qt:desc"].iter() {
+                    let mut args: Vec<String> = filter_args.iter().map(|arg| arg.to_string()).collect();
+
+                    args.push("-s".to_string());
+                    args.push(sort.to_string());
+
+                    if dedup {
+                        args.push("-d".to_string());
+                    }
+
+                    if let Some(limit) = limit {
+                        args.push("-l".to_string());
+                        args.push(limit.to_string());
+                    }
+
+                    let output = run(&log, &args);
+                    let (entries, filtered, duplicates) = expected(qt_min, regex, count_range, dedup, limit, sort);
+
+                    assert_eq!(output.total, ENTRIES.len(), "{:?}", args);
+                    assert_eq!(output.filtered, filtered, "{:?}", args);
+                    assert_eq!(output.duplicates, duplicates, "{:?}", args);
+                    assert_eq!(output.shown, entries.len(), "{:?}", args);
+                    assert_eq!(output.entries.len(), entries.len(), "{:?}", args);
+
+                    for (entry, expected) in output.entries.iter().zip(entries.iter()) {
+                        assert_eq!(entry.query, expected.2, "{:?}", args);
+                        assert_eq!(entry.query_time, expected.1, "{:?}", args);
+                        assert_eq!(entry.count, count(&expected.2), "{:?}", args);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn limit_is_filled_after_count_filter() {
+    let log = log_file("limit");
+    let args: Vec<String> = vec!["--cnt_min", "3", "-l", "4", "-s", "qt:desc"].iter()
+        .map(|arg| arg.to_string()).collect();
+
+    let output = run(&log, &args);
+
+    assert_eq!(output.shown, 4);
+    assert_eq!(output.filtered, 3);
+    assert!(output.entries.iter().all(|entry| entry.count >= 3));
+    assert_eq!(output.entries.iter().map(|entry| entry.query_time).collect::<Vec<f64>>(),
+               vec![3.0, 2.0, 1.0, 0.5]);
+}
+
+#[test]
+fn accounting_adds_up() {
+    let log = log_file("accounting");
+    let args: Vec<String> = vec!["-d", "--qt_min", "0.1", "--cnt_max", "3"].iter()
+        .map(|arg| arg.to_string()).collect();
+
+    let output = run(&log, &args);
+
+    assert_eq!(output.total, output.filtered + output.duplicates + output.shown);
+}
+
+#[test]
+fn dedup_keeps_chosen_query_per_fingerprint() {
+    let log = write_log("dedup", &[
+        (0, 0.10, 300, "SELECT * FROM users WHERE id = 1;"),
+        (1, 0.90, 5, "SELECT * FROM users WHERE id = 2;"),
+        (2, 2.00, 1, "DELETE FROM sessions WHERE expired = 1;"),
+        (3, 0.30, 100, "SELECT * FROM users WHERE id = 3;"),
+        (4, 0.20, 7, "SELECT * FROM users WHERE id = 4;")
+    ]);
+
+    let cases = vec![
+        (vec!["-d"], "SELECT * FROM users WHERE id = 4;"),
+        (vec!["--dedup", "last"], "SELECT * FROM users WHERE id = 4;"),
+        (vec!["-d", "first"], "SELECT * FROM users WHERE id = 1;"),
+        (vec!["-d", "slowest"], "SELECT * FROM users WHERE id = 2;"),
+        (vec!["-d", "examined"], "SELECT * FROM users WHERE id = 1;")
+    ];
+
+    for (dedup_args, kept) in cases.into_iter() {
+        let mut args: Vec<String> = dedup_args.iter().map(|arg| arg.to_string()).collect();
+
+        args.push("-s".to_string());
+        args.push("qt".to_string());
+
+        let output = run(&log, &args);
+
+        assert_eq!(output.total, 5, "{:?}", args);
+        assert_eq!(output.duplicates, 3, "{:?}", args);
+        assert_eq!(output.shown, 2, "{:?}", args);
+
+        let users = output.entries.iter().find(|entry| entry.query.contains("users")).unwrap();
+
+        assert_eq!(users.query, kept, "{:?}", args);
+        assert_eq!(users.group.as_ref().unwrap(),
+                   "GROUP_COUNT: 4 | TOTAL_TIME: 1.500000 | AVG_TIME: 0.375000 | MAX_TIME: 0.900000 | ROWS_EXAMINED: 412",
+                   "{:?}", args);
+
+        let sessions = output.entries.iter().find(|entry| entry.query.contains("sessions")).unwrap();
+
+        assert_eq!(sessions.query_time, 2.0);
+        assert_eq!(sessions.rows_examined, 1);
+        assert_eq!(sessions.group.as_ref().unwrap(),
+                   "GROUP_COUNT: 1 | TOTAL_TIME: 2.000000 | AVG_TIME: 2.000000 | MAX_TIME: 2.000000 | ROWS_EXAMINED: 1");
+    }
+}
+
+#[test]
+fn dedup_keeps_chosen_query_among_filtered() {
+    let log = write_log("dedup-filtered", &[
+        (0, 0.10, 300, "SELECT * FROM users WHERE id = 1;"),
+        (1, 1.50, 5, "SELECT * FROM users WHERE id = 2;"),
+        (2, 0.90, 7, "SELECT * FROM users WHERE id = 3;"),
+        (3, 2.00, 1, "DELETE FROM sessions WHERE expired = 1;")
+    ]);
+
+    let args: Vec<String> = vec!["-d", "slowest", "--qt_max", "1"].iter().map(|arg| arg.to_string()).collect();
+    let output = run(&log, &args);
+
+    assert_eq!(output.total, 4);
+    assert_eq!(output.filtered, 2);
+    assert_eq!(output.duplicates, 1);
+    assert_eq!(output.shown, 1);
+    assert_eq!(output.entries[0].query, "SELECT * FROM users WHERE id = 3;");
+    assert_eq!(output.entries[0].group.as_ref().unwrap(),
+               "GROUP_COUNT: 2 | TOTAL_TIME: 1.000000 | AVG_TIME: 0.500000 | MAX_TIME: 0.900000 | ROWS_EXAMINED: 307");
+}
+
+#[test]
+fn lint_reports_rules_by_total_time() {
+    let log = write_log("lint", &[
+        (0, 0.50, 10, "SELECT * FROM users WHERE id = 1;"),
+        (1, 2.00, 10, "SELECT name FROM products WHERE name LIKE '%phone' LIMIT 10;"),
+        (2, 1.00, 10, "UPDATE users SET active = 0;"),
+        (3, 0.70, 10, "SELECT id FROM orders WHERE DATE(created_at) = '2018-09-01';"),
+        (4, 0.90, 10, "SELECT id FROM orders LIMIT 20 OFFSET 50000;"),
+        (5, 0.30, 10, "SELECT id FROM users WHERE EXISTS (SELECT * FROM bans WHERE bans.user_id = users.id);"),
+        (6, 0.25, 10, "SELECT * FROM users WHERE id = 2;")
+    ]);
+
+    let lint_rows = |lint: &[&str]| -> Vec<(String, String)> {
+        let output = Command::new(binary())
+            .arg("-f").arg(&log)
+            .args(lint)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success(), "{:?} failed", lint);
+
+        String::from_utf8(output.stdout).unwrap().lines()
+            .skip_while(|line| !line.starts_with("LINT REPORT:"))
+            .skip(2)
+            .take_while(|line| line.len() > 0)
+            .map(|line| {
+                let columns: Vec<&str> = line.split_whitespace().collect();
+
+                (columns[0].to_string(), columns[2].to_string())
+            })
+            .collect()
+    };
+
+    assert_eq!(lint_rows(&["--lint"]), vec![
+        ("leading_wildcard".to_string(), "2.000000".to_string()),
+        ("no_where".to_string(), "1.000000".to_string()),
+        ("large_offset".to_string(), "0.900000".to_string()),
+        ("select_star".to_string(), "0.750000".to_string()),
+        ("function_on_column".to_string(), "0.700000".to_string())
+    ]);
+
+    let rules = |lint: &[&str]| -> Vec<String> {
+        lint_rows(lint).into_iter().map(|row| row.0).collect()
+    };
+
+    assert_eq!(rules(&["--lint=all"]).len(), 5);
+    assert_eq!(rules(&["--lint=-leading_wildcard,-select_star"]), vec!["no_where", "large_offset", "function_on_column"]);
+    assert_eq!(rules(&["--lint", "-leading_wildcard,-select_star"]), vec!["no_where", "large_offset", "function_on_column"]);
+    assert_eq!(rules(&["--lint", "-select_star", "-l", "1"]), vec!["leading_wildcard", "no_where", "large_offset", "function_on_column"]);
+    assert_eq!(rules(&["--lint", "no_where"]), vec!["no_where"]);
+
+    let output = Command::new(binary()).arg("-f").arg(&log).args(&["--format", "json", "--lint", "no_where,select_star"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lint = [
+        "  \"lint\": [",
+        "    {\"rule\": \"no_where\", \"count\": 1, \"total_time\": 1, \"fingerprint\": \"UPDATE users SET active = |NUMBER| ;\"},",
+        "    {\"rule\": \"select_star\", \"count\": 2, \"total_time\": 0.75, \"fingerprint\": \"SELECT * FROM users WHERE id = |NUMBER| ;\"}",
+        "  ],"
+    ].join("\n");
+
+    assert!(stdout.contains(&lint), "{}", stdout);
+}
+
+#[test]
+fn gate_exit_codes() {
+    let log = log_file("gate");
+    let allowlist = env::temp_dir().join(format!("myslowparser-gate-{}.txt", std::process::id()));
+
+    File::create(&allowlist).unwrap()
+        .write_all(b"# known queries\nSELECT * FROM users WHERE id = 7;\nSELECT * FROM orders WHERE id = |NUMBER| ;\n").unwrap();
+
+    let exit_code = |args: &[&str]| -> i32 {
+        Command::new(binary()).arg("-f").arg(&log).args(args).output().unwrap().status.code().unwrap()
+    };
+
+    let allowlist = allowlist.to_str().unwrap();
+
+    assert_eq!(exit_code(&[]), 0);
+    assert_eq!(exit_code(&["--fail_if", "qt > 5.0"]), 0);
+    assert_eq!(exit_code(&["--fail_if", "qt >= 5.0"]), 1);
+    assert_eq!(exit_code(&["--fail_if", "qt >= 5.0", "--where", "stmt != 'DELETE'"]), 0);
+    assert_eq!(exit_code(&["--max_fp_time", "6.0"]), 0);
+    assert_eq!(exit_code(&["--max_fp_time", "5.5"]), 1);
+    assert_eq!(exit_code(&["--fail_if_new_fingerprint", allowlist, "--where", "stmt = 'SELECT'"]), 0);
+    assert_eq!(exit_code(&["--fail_if_new_fingerprint", allowlist]), 1);
+    assert_eq!(exit_code(&["--fail_if", "qt >"]), 2);
+    assert_eq!(exit_code(&["--no_such_flag"]), 2);
+    assert_eq!(exit_code(&["--state_file", "myslowparser-web.state", "--web", "8080"]), 2);
+    assert_eq!(Command::new(binary()).arg("-f").arg(env::temp_dir().join("myslowparser-missing.log"))
+                   .output().unwrap().status.code().unwrap(), 3);
+}
+
+#[test]
+fn baseline_round_trip_and_thresholds() {
+    // Same log, users queries 1.5 times slower: p95 goes 0.5 -> 0.75, z-score 1.77
+    let log_with_users_slower = |name: &str, factor: f64| -> PathBuf {
+        let entries: Vec<(i64, f64, i64, &str)> = ENTRIES.iter()
+            .map(|&(offset, query_time, query)| {
+                (offset, if query.contains("users") { query_time * factor } else { query_time }, 10, query)
+            })
+            .collect();
+
+        write_log(name, &entries)
+    };
+
+    let log = log_with_users_slower("baseline", 1.0);
+    let slower = log_with_users_slower("baseline-slower", 1.5);
+    let baseline = env::temp_dir().join(format!("myslowparser-baseline-{}.txt", std::process::id()));
+
+    let anomaly_report_of = |log: &PathBuf, args: &[&str]| -> String {
+        let output = Command::new(binary()).arg("-f").arg(log).arg("--baseline").arg(&baseline)
+            .args(args).output().unwrap();
+
+        assert_eq!(output.status.code(), Some(0));
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let start = stdout.find("ANOMALY REPORT").unwrap();
+
+        stdout[start..].split("\n\n").next().unwrap().to_string()
+    };
+    let anomaly_report = |log: &PathBuf, anomaly: &str| anomaly_report_of(log, &["--anomaly", anomaly]);
+
+    let status = Command::new(binary()).arg("-f").arg(&log).arg("--save_baseline").arg(&baseline).output().unwrap().status;
+
+    assert_eq!(status.code(), Some(0));
+    assert!(anomaly_report(&log, "3").ends_with("\n\tNone"));
+    assert!(anomaly_report(&log, "1%").ends_with("\n\tNone"));
+
+    for &(anomaly, flagged) in [("40%", true), ("60%", false), ("1.5", true), ("2", false)].iter() {
+        let report = anomaly_report(&slower, anomaly);
+
+        if flagged {
+            assert_eq!(report.lines().count(), 3, "--anomaly {}:\n{}", anomaly, report);
+            assert!(report.contains("p95 "), "--anomaly {}:\n{}", anomaly, report);
+            assert!(report.contains("50.0% SELECT * FROM users WHERE id = |NUMBER| ;"), "--anomaly {}:\n{}", anomaly, report);
+        } else {
+            assert!(report.ends_with("\n\tNone"), "--anomaly {}:\n{}", anomaly, report);
+        }
+    }
+
+    // Filtered out DELETE vanishes, its rate drops from 1 to 0 per minute: -100%, z-score -1
+    let vanished = anomaly_report_of(&log, &["--anomaly", "50%", "--where", "stmt != 'DELETE'"]);
+
+    assert!(vanished.ends_with("\nrate         1.000000       0.000000      -1.00    -100.0% DELETE FROM sessions WHERE expired = |NUMBER| ;"),
+            "{}", vanished);
+    assert!(anomaly_report_of(&log, &["--anomaly", "1.5", "--where", "stmt != 'DELETE'"]).ends_with("\n\tNone"));
+
+    let status = Command::new(binary()).arg("-f").arg(&log).arg("--save_baseline")
+        .arg(env::temp_dir().join("myslowparser-missing").join("baseline.txt")).output().unwrap().status;
+
+    assert_eq!(status.code(), Some(4));
+}
+
+#[test]
+fn allowlist_lines_are_fingerprinted_like_log_queries() {
+    let log = write_log("allowlist", &[
+        (0, 0.1, 10, "SELECT * FROM users WHERE id = 1;"),
+        (1, 0.2, 10, "/* controller:orders */ SELECT *\n  FROM orders WHERE id = 2;"),
+        (2, 0.3, 10, "SELECT name FROM users WHERE login IN ('a','b');"),
+        (3, 5.0, 10, "DELETE FROM sessions WHERE expired = 1;")
+    ]);
+    let allowlist = env::temp_dir().join(format!("myslowparser-allowlist-{}.txt", std::process::id()));
+
+    // A commented query with extra spaces, a query with other literals and two fingerprints as printed
+    let lines = [
+        "/* app:web */ SELECT  *  FROM users\tWHERE id = 7;",
+        "SELECT * FROM orders /* shard:2 */ WHERE id = 42 ;",
+        "SELECT name FROM users WHERE login IN (|STRING| , |STRING|);",
+        "DELETE FROM sessions WHERE expired = |NUMBER| ;"
+    ];
+
+    let exit_code = |skipped: Option<usize>| -> i32 {
+        let mut file = File::create(&allowlist).unwrap();
+
+        file.write_all(b"# known queries\n\n").unwrap();
+
+        for (index, line) in lines.iter().enumerate() {
+            if Some(index) != skipped {
+                writeln!(file, "  {}", line).unwrap();
+            }
+        }
+
+        Command::new(binary()).arg("-f").arg(&log).arg("--fail_if_new_fingerprint").arg(&allowlist)
+            .output().unwrap().status.code().unwrap()
+    };
+
+    assert_eq!(exit_code(None), 0);
+
+    for (index, line) in lines.iter().enumerate() {
+        assert_eq!(exit_code(Some(index)), 1, "without {}", line);
+    }
+}
+
+#[test]
+fn reports_see_duplicates_when_deduplicated() {
+    let log = log_file("dedup-reports");
+
+    let reports = |args: &[&str]| -> String {
+        let output = Command::new(binary()).arg("-f").arg(&log).args(args)
+            .args(&["--report", "summary", "--group_by", "user"]).output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let start = stdout.find("SUMMARY REPORT").unwrap();
+        let end = stdout.find("TOTAL: ").unwrap();
+
+        stdout[start..end].to_string()
+    };
+
+    let all = reports(&[]);
+
+    assert!(all.contains("\tQueries: 11\n"), "{}", all);
+    assert!(all.contains("\napp          11 "), "{}", all);
+
+    for keep in ["slowest", "first"].iter() {
+        assert_eq!(reports(&["-d", keep]), all, "-d {}", keep);
+    }
+}
+
+#[test]
+fn old_style_time_is_taken_in_timezone() {
+    let log = env::temp_dir().join(format!("myslowparser-old-time-{}.log", std::process::id()));
+
+    File::create(&log).unwrap().write_all(b"# Time: 180912 10:00:05\n\
+        # User@Host: app[app] @ web1 [10.0.0.5]  Id:    12\n\
+        # Query_time: 0.100000  Lock_time: 0.000000 Rows_sent: 1  Rows_examined: 10\n\
+        SELECT 1;\n").unwrap();
+
+    let date_times = |args: &[&str]| -> Vec<String> {
+        let output = Command::new(binary()).arg("-f").arg(&log).args(args).output().unwrap();
+
+        String::from_utf8(output.stdout).unwrap().lines()
+            .filter(|line| line.starts_with("> #"))
+            .map(|line| field(line, "DATE_TIME").to_string())
+            .collect()
+    };
+
+    assert_eq!(date_times(&[]), vec!["12/09/2018:10:00:05"]);
+    assert_eq!(date_times(&["--timezone", "+03:00", "--display_timezone", "UTC"]), vec!["12/09/2018:07:00:05"]);
+    assert_eq!(date_times(&["--timezone", "+03:00", "--ts_min", "2018-09-12 10:00:05"]).len(), 1);
+    assert_eq!(date_times(&["--timezone", "+03:00", "--ts_min", "2018-09-12 10:00:06"]).len(), 0);
+    assert_eq!(date_times(&["--ts_min", "2018-09-12 07:00:06"]).len(), 1);
+}
+
+#[test]
+fn json_output_uses_display_timezone_and_format() {
+    let log = write_log("json", &[
+        (0, 0.5, 10, "SELECT * FROM users WHERE name = \"a\\\\b\";"),
+        (1, 1.5, 20, "/* controller:users */ SELECT *\n  FROM users WHERE id = 1;")
+    ]);
+    let output = Command::new(binary()).arg("-f").arg(&log)
+        .args(&["--format", "json", "--display_timezone", "+03:00", "--dt_format", "%Y-%m-%d %H:%M:%S", "-v", "raw"])
+        .output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let entries: Vec<&str> = stdout.lines().filter(|line| line.starts_with("    {")).collect();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout.starts_with("{\n  \"queries\": [\n"), "{}", stdout);
+    assert!(stdout.ends_with("  \"timezone\": \"+03:00\",\n  \"total\": 2,\n  \"filtered\": 0,\n  \"duplicates\": 0,\n  \"shown\": 2\n}\n"), "{}", stdout);
+    assert_eq!(entries.len(), 2, "{}", stdout);
+    assert!(entries[0].starts_with("    {\"date_time\": \"2018-09-12 13:00:00\", \"timestamp\": 1536746400, \"db\": null, "), "{}", entries[0]);
+    assert!(entries[0].ends_with("\"query\": \"SELECT * FROM users WHERE name = \\\"a\\\\\\\\b\\\";\"},"), "{}", entries[0]);
+    assert!(entries[1].contains("\"query_time\": 1.5, "), "{}", entries[1]);
+    assert!(entries[1].contains("\"tags\": {\"controller\": \"users\"}, "), "{}", entries[1]);
+    assert!(entries[1].ends_with("\"query\": \"/* controller:users */ SELECT *\\n  FROM users WHERE id = 1;\"}"), "{}", entries[1]);
+
+    let status = Command::new(binary()).arg("-f").arg(&log).args(&["--format", "json", "--report", "summary"]).output().unwrap().status;
+
+    assert_eq!(status.code(), Some(2));
+}